@@ -0,0 +1,24 @@
+use crate::*;
+
+// bid history of auctions
+
+//a bid as it was placed (or revealed, for sealed auctions)
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Bid {
+    pub bidder_id: AccountId,
+    pub amount: U128,
+    pub timestamp: U64,
+}
+
+//the most recent MAX_BID_HISTORY bids on a token's last auction, oldest first. Bids are numbered in the order
+//they were placed and only the numbers from `first` to `first + len` are still kept. The history outlives the
+//listing so sales can still be disputed after they're settled, and is only dropped when the token is listed again
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct BidHistory {
+    //the bidder whose storage balance pays for the history itself (its first bidder)
+    pub created_by: AccountId,
+    pub first: u64,
+    pub len: u64,
+    pub bids: LookupMap<u64, Bid>,
+}
@@ -0,0 +1,264 @@
+use crate::*;
+use crate::fees::OWNER_CUT_TIMELOCK;
+use std::collections::HashMap;
+
+// per collection configuration
+
+//royalties can't add up to more than this, in basis points (50%)
+pub const MAX_FALLBACK_ROYALTIES: u32 = 5_000;
+
+//which nft contracts can list on the marketplace
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum CollectionMode {
+    //any contract
+    Open,
+    //only allowlisted contracts
+    Allowlist,
+    //any contract that isn't blocklisted
+    Blocklist,
+}
+
+//where an nft contract stands with the marketplace, as returned by get_collection_status
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionStatus {
+    pub mode: CollectionMode,
+    pub allowlisted: bool,
+    pub blocklisted: bool,
+    //whether the contract's tokens can be listed under the current mode
+    pub can_list: bool,
+    pub verified: bool,
+}
+
+//terms the marketplace has agreed with an nft contract
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionConfig {
+    //the marketplace's cut of the collection's sales in basis points, instead of owner_cut. None uses owner_cut
+    pub fee: Option<u16>,
+    //set for contracts that don't implement nft_transfer_payout. Their tokens are sold with nft_transfer and these
+    //royalties (in basis points of what's left after the fee) are paid out instead, the rest going to the seller
+    pub fallback_royalties: Option<HashMap<AccountId, u16>>,
+    //whether the marketplace has verified the collection is what it claims to be
+    #[serde(default)]
+    pub verified: bool,
+    //a fee increase that's waiting out OWNER_CUT_TIMELOCK. Only the marketplace sets this
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub pending_fee: Option<PendingCollectionFee>,
+}
+
+//the fee a collection moves to at effective_at. A fee of None means it moves to owner_cut
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingCollectionFee {
+    pub fee: Option<u16>,
+    pub effective_at: U64,
+}
+
+impl CollectionConfig {
+    //the fee that applies right now, including an increase whose timelock has passed
+    pub(crate) fn current_fee(&self) -> Option<u16> {
+        match self.pending_fee.as_ref() {
+            Some(pending) if env::block_timestamp() >= pending.effective_at.0 => pending.fee,
+            _ => self.fee,
+        }
+    }
+
+    //the payout for `balance` under the fallback royalties. The seller's share is left for resolve_purchase to add
+    pub(crate) fn fallback_payout(&self, balance: u128) -> Option<HashMap<AccountId, U128>> {
+        self.fallback_royalties.as_ref().map(|royalties| {
+            royalties
+                .iter()
+                .map(|(account_id, royalty)| {
+                    (account_id.clone(), U128(balance * u128::from(*royalty) / 10_000))
+                })
+                .collect()
+        })
+    }
+}
+
+#[near_bindgen]
+impl Marketplace {
+    //Sets the terms for an nft contract's sales, replacing any it had. Like owner_cut, a fee that's higher than the
+    //one the collection pays now only applies OWNER_CUT_TIMELOCK later. Only the owner or a fees admin can do this
+    pub fn set_collection_config(&mut self, nft_contract_id: AccountId, config: CollectionConfig) {
+        self.assert_role(AdminRole::Fees);
+        if let Some(fee) = config.fee {
+            assert!(fee <= MAX_OWNER_CUT, "fee can't be more than {}", MAX_OWNER_CUT);
+        }
+        if let Some(royalties) = config.fallback_royalties.as_ref() {
+            assert!(
+                royalties.len() < MAX_LEN_PAYOUT as usize,
+                "Too many fallback royalties"
+            );
+            assert!(
+                royalties.values().map(|royalty| u32::from(*royalty)).sum::<u32>()
+                    <= MAX_FALLBACK_ROYALTIES,
+                "Fallback royalties can't add up to more than {}",
+                MAX_FALLBACK_ROYALTIES
+            );
+        }
+
+        //the collection keeps paying what it pays now until an increase has waited out the timelock
+        let config = if config.fee.unwrap_or_else(|| self.internal_owner_cut())
+            > self.internal_collection_fee(&nft_contract_id)
+        {
+            CollectionConfig {
+                fee: self
+                    .collection_configs
+                    .get(&nft_contract_id)
+                    .and_then(|config| config.current_fee()),
+                pending_fee: Some(PendingCollectionFee {
+                    fee: config.fee,
+                    effective_at: U64(env::block_timestamp() + OWNER_CUT_TIMELOCK),
+                }),
+                ..config
+            }
+        } else {
+            config
+        };
+
+        self.collection_configs.insert(&nft_contract_id, &config);
+        EventLogVariant::CollectionConfigSet(CollectionConfigLog {
+            nft_contract_id,
+            config: Some(config),
+        })
+        .emit();
+    }
+
+    //Removes an nft contract's terms, so its sales go back to owner_cut and nft_transfer_payout. If owner_cut is
+    //higher than the collection's fee, only the fee is kept until the move to owner_cut has waited out the timelock.
+    //Only the owner or a fees admin can do this
+    pub fn remove_collection_config(&mut self, nft_contract_id: AccountId) {
+        self.assert_role(AdminRole::Fees);
+        let current_fee = self.internal_collection_fee(&nft_contract_id);
+        let old_config = self
+            .collection_configs
+            .remove(&nft_contract_id)
+            .expect("No config for this collection");
+
+        let config = (self.internal_owner_cut() > current_fee).then(|| CollectionConfig {
+                fee: old_config.current_fee(),
+                fallback_royalties: None,
+                verified: false,
+                pending_fee: Some(PendingCollectionFee {
+                    fee: None,
+                    effective_at: U64(env::block_timestamp() + OWNER_CUT_TIMELOCK),
+                }),
+            });
+        if let Some(config) = config.as_ref() {
+            self.collection_configs.insert(&nft_contract_id, config);
+        }
+        EventLogVariant::CollectionConfigRemoved(CollectionConfigLog {
+            nft_contract_id,
+            config,
+        })
+        .emit();
+    }
+
+    //Sets which nft contracts can list on the marketplace. Listings that already exist aren't affected. Only the
+    //owner or an allowlist admin can do this
+    pub fn set_collection_mode(&mut self, mode: CollectionMode) {
+        self.assert_role(AdminRole::Allowlist);
+        self.collection_mode = mode;
+        EventLogVariant::CollectionModeChanged(CollectionModeLog { mode }).emit();
+    }
+
+    //Adds nft contracts to the allowlist, or removes them from it when `allowlisted` is false. Only the owner or an
+    //allowlist admin can do this
+    pub fn set_allowlisted(&mut self, nft_contract_ids: Vec<AccountId>, allowlisted: bool) {
+        self.assert_role(AdminRole::Allowlist);
+        for nft_contract_id in nft_contract_ids.iter() {
+            if allowlisted {
+                self.allowlisted_collections.insert(nft_contract_id);
+            } else {
+                self.allowlisted_collections.remove(nft_contract_id);
+            }
+        }
+        EventLogVariant::CollectionsAllowlisted(CollectionListLog {
+            nft_contract_ids,
+            listed: allowlisted,
+        })
+        .emit();
+    }
+
+    //Adds nft contracts to the blocklist, or removes them from it when `blocklisted` is false. Only the owner or an
+    //allowlist admin can do this
+    pub fn set_blocklisted(&mut self, nft_contract_ids: Vec<AccountId>, blocklisted: bool) {
+        self.assert_role(AdminRole::Allowlist);
+        for nft_contract_id in nft_contract_ids.iter() {
+            if blocklisted {
+                self.blocklisted_collections.insert(nft_contract_id);
+            } else {
+                self.blocklisted_collections.remove(nft_contract_id);
+            }
+        }
+        EventLogVariant::CollectionsBlocklisted(CollectionListLog {
+            nft_contract_ids,
+            listed: blocklisted,
+        })
+        .emit();
+    }
+
+    //returns the listing mode, whether an nft contract is on either list, and whether it can list right now
+    pub fn get_collection_status(&self, nft_contract_id: AccountId) -> CollectionStatus {
+        CollectionStatus {
+            mode: self.collection_mode,
+            allowlisted: self.allowlisted_collections.contains(&nft_contract_id),
+            blocklisted: self.blocklisted_collections.contains(&nft_contract_id),
+            can_list: self.internal_can_list(&nft_contract_id),
+            verified: self
+                .collection_configs
+                .get(&nft_contract_id)
+                .is_some_and(|config| config.verified),
+        }
+    }
+
+    //returns the terms the marketplace has for an nft contract, if any
+    pub fn get_collection_config(&self, nft_contract_id: AccountId) -> Option<CollectionConfig> {
+        self.collection_configs.get(&nft_contract_id)
+    }
+
+    //returns paginated nft contracts and their terms
+    pub fn get_collection_configs(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<(AccountId, CollectionConfig)> {
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+        self.collection_configs
+            .iter()
+            .skip(start as usize)
+            .take(limit.unwrap_or(0) as usize)
+            .collect()
+    }
+}
+
+impl Marketplace {
+    //the fee `nft_contract_id`'s sales pay right now, in basis points
+    pub(crate) fn internal_collection_fee(&self, nft_contract_id: &AccountId) -> u16 {
+        self.collection_configs
+            .get(nft_contract_id)
+            .and_then(|config| config.current_fee())
+            .unwrap_or_else(|| self.internal_owner_cut())
+    }
+
+    //whether tokens from `nft_contract_id` can be listed under the current mode
+    pub(crate) fn internal_can_list(&self, nft_contract_id: &AccountId) -> bool {
+        match self.collection_mode {
+            CollectionMode::Open => true,
+            CollectionMode::Allowlist => self.allowlisted_collections.contains(nft_contract_id),
+            CollectionMode::Blocklist => !self.blocklisted_collections.contains(nft_contract_id),
+        }
+    }
+
+    //used to make sure tokens from `nft_contract_id` can be listed
+    pub(crate) fn assert_can_list(&self, nft_contract_id: &AccountId) {
+        assert!(
+            self.internal_can_list(nft_contract_id),
+            "{} is not an approved nft contract",
+            nft_contract_id
+        );
+    }
+}
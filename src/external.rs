@@ -1,9 +1,18 @@
 use crate::*;
+use std::collections::HashMap;
 
-/// external contract calls
+//the payout object returned by nft_transfer_payout (NEP-199). It maps each account to the amount it should receive
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Payout {
+    pub payout: HashMap<AccountId, U128>,
+}
+
+// external contract calls
 
 //initiate a cross contract call to the nft contract. This will transfer the token to the buyer and return
 //a payout object used for the market to distribute funds to the appropriate accounts.
+#[allow(dead_code)]
 #[ext_contract(ext_contract)]
 trait ExtContract {
     fn nft_transfer_payout(
@@ -20,4 +29,28 @@ trait ExtContract {
         //the maximum amount of accounts the market can payout at once (this is limited by GAS)
 		max_len_payout: u32,
     );
-}
\ No newline at end of file
+
+    //transfer the token to the buyer without a payout, for contracts that don't implement nft_transfer_payout (NEP-171)
+    fn nft_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+    );
+
+    //check that the marketplace still holds the approval a listing was created with (NEP-178)
+    fn nft_is_approved(
+        &self,
+        token_id: TokenId,
+        approved_account_id: AccountId,
+        approval_id: Option<u64>,
+    ) -> bool;
+}
+
+//pay out a sale, refund or bid in a fungible token
+#[allow(dead_code)]
+#[ext_contract(ext_ft)]
+trait ExtFungibleToken {
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+}
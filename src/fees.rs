@@ -0,0 +1,102 @@
+use crate::*;
+
+// the marketplace fee and where it's paid
+
+//the most owner_cut can be set to, in basis points (10%)
+pub const MAX_OWNER_CUT: u16 = 1_000;
+//how long an increase of owner_cut waits before it applies, so sellers can see it coming (1 day in nanoseconds)
+pub const OWNER_CUT_TIMELOCK: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+//an increase of owner_cut that's waiting out the timelock
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct PendingOwnerCut {
+    pub owner_cut: u16,
+    pub effective_at: u64,
+}
+
+//the marketplace fee, as returned by get_fee_config
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeConfig {
+    //the cut of every sale the marketplace takes right now, in basis points
+    pub owner_cut: u16,
+    pub max_owner_cut: u16,
+    //the account fees are paid to
+    pub treasury_id: AccountId,
+    //an increase that applies at pending_effective_at. Both are left out when there isn't one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending_owner_cut: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending_effective_at: Option<U64>,
+}
+
+#[near_bindgen]
+impl Marketplace {
+    //Sets the marketplace's cut of every sale, in basis points, up to MAX_OWNER_CUT. Lowering it applies straight
+    //away, while an increase only applies OWNER_CUT_TIMELOCK after it's made. Setting it again replaces an increase
+    //that hasn't applied yet. Only the owner or a fees admin can do this
+    pub fn set_owner_cut(&mut self, owner_cut: u16) {
+        self.assert_role(AdminRole::Fees);
+        assert!(
+            owner_cut <= MAX_OWNER_CUT,
+            "owner_cut can't be more than {}",
+            MAX_OWNER_CUT
+        );
+
+        //an increase that has already applied becomes the cut we compare against
+        self.owner_cut = self.internal_owner_cut();
+        let effective_at = if owner_cut > self.owner_cut {
+            let effective_at = env::block_timestamp() + OWNER_CUT_TIMELOCK;
+            self.pending_owner_cut = Some(PendingOwnerCut {
+                owner_cut,
+                effective_at,
+            });
+            effective_at
+        } else {
+            self.pending_owner_cut = None;
+            self.owner_cut = owner_cut;
+            env::block_timestamp()
+        };
+        EventLogVariant::OwnerCutChanged(OwnerCutLog {
+            owner_cut,
+            effective_at: U64(effective_at),
+        })
+        .emit();
+    }
+
+    //Sets the account marketplace fees are paid to. Only the owner can do this
+    pub fn set_treasury(&mut self, treasury_id: AccountId) {
+        self.assert_owner();
+        let old_treasury_id = std::mem::replace(&mut self.treasury_id, treasury_id.clone());
+        EventLogVariant::TreasuryChanged(TreasuryLog {
+            old_treasury_id,
+            treasury_id,
+        })
+        .emit();
+    }
+
+    //returns the marketplace fee, where it's paid and any increase that's waiting to apply
+    pub fn get_fee_config(&self) -> FeeConfig {
+        let pending = self
+            .pending_owner_cut
+            .as_ref()
+            .filter(|pending| env::block_timestamp() < pending.effective_at);
+        FeeConfig {
+            owner_cut: self.internal_owner_cut(),
+            max_owner_cut: MAX_OWNER_CUT,
+            treasury_id: self.treasury_id.clone(),
+            pending_owner_cut: pending.map(|pending| pending.owner_cut),
+            pending_effective_at: pending.map(|pending| U64(pending.effective_at)),
+        }
+    }
+}
+
+impl Marketplace {
+    //the cut the marketplace takes right now, including an increase whose timelock has passed
+    pub(crate) fn internal_owner_cut(&self) -> u16 {
+        match self.pending_owner_cut.as_ref() {
+            Some(pending) if env::block_timestamp() >= pending.effective_at => pending.owner_cut,
+            _ => self.owner_cut,
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::*;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+
+// transfer callbacks from FT Contracts
+
+//what a buyer wants to do with the tokens they sent with ft_transfer_call. It's passed as the `msg`
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde", tag = "action", rename_all = "snake_case")]
+pub enum FtTransferArgs {
+    //buy a fixed price listing, or an auction at its buy now price. Anything sent above the price is returned to the sender
+    Purchase {
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+    },
+    //bid the whole amount on an auction
+    Bid {
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+    },
+    //offer to buy up to quantity tokens of an nft contract for price each. Anything sent above the total is returned
+    CollectionOffer {
+        nft_contract_id: AccountId,
+        price: U128,
+        quantity: u32,
+    },
+}
+
+/*
+    trait that will be used as the callback from a fungible token contract. When ft_transfer_call is
+    called with the marketplace as the receiver, the tokens are transferred to us and this function is
+    invoked. Whatever we return is refunded to the sender, and if we panic they get everything back.
+*/
+#[near_bindgen]
+impl FungibleTokenReceiver for Marketplace {
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        //the fungible token is the contract calling us
+        let ft_token_id = env::predecessor_account_id();
+        assert!(
+            self.ft_token_ids.contains(&ft_token_id),
+            "{} is not an accepted fungible token",
+            ft_token_id
+        );
+
+        let transfer_args: FtTransferArgs = near_sdk::serde_json::from_str(&msg)
+            .unwrap_or_else(|err| panic!("Invalid ft_transfer_call msg: {}", err));
+        match transfer_args {
+            FtTransferArgs::Purchase {
+                nft_contract_id,
+                token_id,
+            } => {
+                let contract_and_token_id =
+                    format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+                let listing = self
+                    .listings
+                    .get(&contract_and_token_id)
+                    .expect("NFT not listed yet");
+                assert!(
+                    listing.ft_token_id.as_ref() == Some(&ft_token_id),
+                    "Wrong currency for this listing"
+                );
+
+                //we keep exactly the price and let the fungible token contract refund the rest
+                let price = listing.purchase_price();
+                assert!(amount.0 >= price, "Sent amount must be at least {}", price);
+                self.internal_purchase(listing, sender_id, price);
+                PromiseOrValue::Value(U128(amount.0 - price))
+            }
+            FtTransferArgs::Bid {
+                nft_contract_id,
+                token_id,
+            } => {
+                self.internal_place_bid(
+                    nft_contract_id,
+                    token_id,
+                    sender_id,
+                    amount.0,
+                    Some(ft_token_id),
+                );
+                PromiseOrValue::Value(U128(0))
+            }
+            FtTransferArgs::CollectionOffer {
+                nft_contract_id,
+                price,
+                quantity,
+            } => {
+                let total = price
+                    .0
+                    .checked_mul(u128::from(quantity))
+                    .expect("Offer total overflows");
+                assert!(amount.0 >= total, "Sent amount must be at least {}", total);
+
+                self.internal_add_collection_offer(
+                    nft_contract_id,
+                    sender_id,
+                    price.0,
+                    quantity,
+                    Some(ft_token_id),
+                );
+                PromiseOrValue::Value(U128(amount.0 - total))
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use crate::*;
+
+// fungible token payouts that couldn't be delivered
+
+#[near_bindgen]
+impl Marketplace {
+    //Called back with the result of every ft_transfer the marketplace makes. If it failed (usually because the
+    //receiver isn't registered with the token contract) the tokens are still here, so they're credited to the
+    //receiver, who can claim them with claim_ft once they've registered
+    #[private]
+    pub fn resolve_ft_transfer(&mut self, ft_token_id: AccountId, receiver_id: AccountId, amount: U128) {
+        if promise_result_as_success().is_some() {
+            return;
+        }
+
+        let key = (receiver_id.clone(), ft_token_id.clone());
+        let claimable = self.ft_claims.get(&key).unwrap_or(0) + amount.0;
+        self.ft_claims.insert(&key, &claimable);
+        EventLogVariant::FtTransferFailed(FtClaimLog {
+            account_id: receiver_id,
+            ft_token_id,
+            amount,
+        })
+        .emit();
+    }
+
+    //Sends the caller whatever they're owed in `ft_token_id` from transfers that failed. If it fails again, it's
+    //credited back so it can be claimed later
+    #[payable]
+    pub fn claim_ft(&mut self, ft_token_id: AccountId) -> Promise {
+        //make sure the user attaches exactly 1 yoctoNEAR for security purposes.
+        assert_one_yocto();
+
+        let account_id = env::predecessor_account_id();
+        let amount = self
+            .ft_claims
+            .remove(&(account_id.clone(), ft_token_id.clone()))
+            .expect("Nothing to claim");
+        EventLogVariant::FtClaimed(FtClaimLog {
+            account_id: account_id.clone(),
+            ft_token_id: ft_token_id.clone(),
+            amount: U128(amount),
+        })
+        .emit();
+        self.internal_transfer(&Some(ft_token_id), account_id, amount)
+    }
+
+    //returns how much `account_id` can claim in `ft_token_id`
+    pub fn get_claimable_ft(&self, account_id: AccountId, ft_token_id: AccountId) -> U128 {
+        U128(self.ft_claims.get(&(account_id, ft_token_id)).unwrap_or(0))
+    }
+}
@@ -9,13 +9,386 @@ pub(crate) fn hash_account_id(account_id: &AccountId) -> CryptoHash {
     hash
 }
 
+//used to generate a unique prefix for collections that belong to a single token
+pub(crate) fn hash_contract_and_token_id(contract_and_token_id: &ContractAndTokenId) -> CryptoHash {
+    let mut hash = CryptoHash::default();
+    hash.copy_from_slice(&env::sha256(contract_and_token_id.as_bytes()));
+    hash
+}
+
+//used to make sure an auction can actually take bids: it has to end after it starts, and not in the past
+pub(crate) fn assert_valid_auction_window(started_at: u64, end_at: u64) {
+    assert!(end_at > started_at, "end_at must be after started_at");
+    assert!(end_at > env::block_timestamp(), "end_at must be in the future");
+}
+
+//used to read the result of an nft_is_approved call. None if the call failed or returned something else
+pub(crate) fn promise_result_as_approval() -> Option<bool> {
+    promise_result_as_success()
+        .and_then(|value| near_sdk::serde_json::from_slice::<bool>(&value).ok())
+}
+
+//checks the expiry of a fixed price listing, which is either 0 (it never expires) or in the future
+pub(crate) fn assert_valid_expiry(expires_at: u64) {
+    assert!(
+        expires_at == 0 || expires_at > env::block_timestamp(),
+        "expires_at must be in the future"
+    );
+}
+
 impl Marketplace {
+    //used to make sure only the marketplace owner can call a method
+    pub(crate) fn assert_owner(&self) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner,
+            "Only the owner can call this method"
+        );
+    }
+
+    //internal method for sending `amount` to `receiver_id` in whatever currency the sale is in. None is NEAR,
+    //otherwise it's the account of the fungible token contract. Fungible tokens that can't be delivered are kept
+    //for the receiver to claim
+    pub(crate) fn internal_transfer(
+        &self,
+        ft_token_id: &Option<AccountId>,
+        receiver_id: AccountId,
+        amount: Balance,
+    ) -> Promise {
+        match ft_token_id {
+            None => Promise::new(receiver_id).transfer(amount),
+            Some(ft_token_id) => ext_ft::ext(ft_token_id.clone())
+                .with_attached_deposit(1)
+                .with_static_gas(GAS_FOR_FT_TRANSFER)
+                .ft_transfer(receiver_id.clone(), U128(amount), None)
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(GAS_FOR_RESOLVE_FT_TRANSFER)
+                        .resolve_ft_transfer(ft_token_id.clone(), receiver_id, U128(amount)),
+                ),
+        }
+    }
+
+    //internal method for placing a bid of `amount` (already received in NEAR or `ft_token_id`) on an auction.
+    //The previous highest bid is refunded since this one replaces it in escrow
+    pub(crate) fn internal_place_bid(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        bidder: AccountId,
+        amount: Balance,
+        ft_token_id: Option<AccountId>,
+    ) {
+        self.assert_not_paused(PauseArea::Bidding);
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        let mut listing = self
+            .listings
+            .get(&contract_and_token_id)
+            .expect("NFT not listed yet");
+        assert!(listing.is_auction, "Not auction");
+        assert!(
+            listing.sealed_auction.is_none(),
+            "Sealed auctions take bids through commit_bid"
+        );
+        assert!(listing.ft_token_id == ft_token_id, "Wrong currency for this listing");
+        assert!(Self::is_on_auction(listing.clone()), "Auction not on");
+        assert!(listing.seller != bidder, "Invalid bid");
+        //the first bid only has to reach the starting price, every one after has to raise by the minimum increment
+        let min_bid = match listing.highest_bidder {
+            Some(_) => listing.min_increment.min_bid(listing.highest_price),
+            None => listing.starting_price.max(1),
+        };
+        assert!(amount >= min_bid, "Invalid price, the minimum bid is {}", min_bid);
+
+        //give the previous highest bidder their escrowed deposit back
+        if let Some(previous_bidder) = listing.highest_bidder {
+            self.internal_transfer(&ft_token_id, previous_bidder.clone(), listing.highest_price);
+            EventLogVariant::OutbidRefund(BidLog {
+                nft_contract_id: nft_contract_id.clone(),
+                token_id: token_id.clone(),
+                bidder_id: previous_bidder,
+                amount: U128(listing.highest_price),
+                end_at: U64(listing.end_at),
+                ft_token_id: ft_token_id.clone(),
+            })
+            .emit();
+        }
+
+        self.internal_record_bid(&contract_and_token_id, &bidder, amount);
+        listing.highest_price = amount;
+        listing.highest_bidder = Some(bidder.clone());
+        //a bid this close to the end gives everyone else time to answer it
+        if listing.end_at - env::block_timestamp() <= listing.extension_window {
+            listing.end_at += listing.extension_duration;
+        }
+        self.listings.insert(&contract_and_token_id, &listing);
+
+        EventLogVariant::BidPlaced(BidLog {
+            nft_contract_id: nft_contract_id.clone(),
+            token_id: token_id.clone(),
+            bidder_id: bidder,
+            amount: U128(amount),
+            end_at: U64(listing.end_at),
+            ft_token_id,
+        })
+        .emit();
+
+        //the bid is held in escrow right away, but if the marketplace turns out to have lost its approval the
+        //auction is called off and the bid refunded
+        self.internal_check_approval(&nft_contract_id, &token_id, listing.approval_id)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_APPROVAL)
+                    .resolve_bid_approval(nft_contract_id, token_id, listing.approval_id),
+            );
+    }
+
+    //internal method for buying a listing with `deposit` (already received in the listing's currency). Fixed price
+    //listings sell at the listing price, and auctions with a buy now price can be bought outright for it, ending the
+    //auction and refunding the highest bid. Anything above the price is refunded to the buyer
+    pub(crate) fn internal_purchase(
+        &mut self,
+        mut listing: Listing,
+        buyer_id: AccountId,
+        deposit: Balance,
+    ) -> Promise {
+        self.assert_not_paused(PauseArea::Buying);
+        assert!(!listing.is_expired(), "Listing has expired");
+        let price = listing.purchase_price();
+        assert!(
+            price <= deposit,
+            "Attached deposit must be at least {}",
+            price
+        );
+
+        let contract_and_token_id =
+            format!("{}{}{}", listing.nft_contract_id, DELIMETER, listing.token_id);
+        if listing.is_auction {
+            assert!(!listing.is_pending, "Purchase in progress");
+            assert!(env::block_timestamp() < listing.end_at, "Auction has ended");
+            assert!(
+                listing.highest_price < price,
+                "Bidding has passed the buy now price"
+            );
+
+            //the auction is over, so the highest bid held in escrow goes back
+            if let Some(highest_bidder) = listing.highest_bidder.take() {
+                self.internal_transfer(
+                    &listing.ft_token_id,
+                    highest_bidder.clone(),
+                    listing.highest_price,
+                );
+                EventLogVariant::OutbidRefund(BidLog {
+                    nft_contract_id: listing.nft_contract_id.parse().unwrap(),
+                    token_id: listing.token_id.clone(),
+                    bidder_id: highest_bidder,
+                    amount: U128(listing.highest_price),
+                    end_at: U64(listing.end_at),
+                    ft_token_id: listing.ft_token_id.clone(),
+                })
+                .emit();
+                listing.highest_price = 0;
+                self.listings.insert(&contract_and_token_id, &listing);
+            }
+        }
+
+        //make sure the seller hasn't revoked the marketplace's approval (or sold the token elsewhere) before
+        //going through with the purchase
+        self.internal_lock_listing(&contract_and_token_id);
+        let nft_contract_id: AccountId = listing.nft_contract_id.parse().unwrap();
+        let marketplace_fee = self.internal_market_fee(&nft_contract_id, price);
+        self.internal_check_approval(&nft_contract_id, &listing.token_id, listing.approval_id)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_PURCHASE_APPROVAL)
+                    .resolve_purchase_approval(
+                        Purchase {
+                            nft_contract_id,
+                            token_id: listing.token_id,
+                            seller: listing.seller,
+                            buyer_id,
+                            price: U128(price),
+                            marketplace_fee: U128(marketplace_fee),
+                            deposit: U128(deposit),
+                            ft_token_id: listing.ft_token_id,
+                        },
+                        listing.approval_id,
+                    ),
+            )
+    }
+
+    //internal method for asking the nft contract whether the marketplace still holds `approval_id` for a token
+    pub(crate) fn internal_check_approval(
+        &self,
+        nft_contract_id: &AccountId,
+        token_id: &TokenId,
+        approval_id: u64,
+    ) -> Promise {
+        ext_contract::ext(nft_contract_id.clone())
+            .with_static_gas(GAS_FOR_NFT_IS_APPROVED)
+            .nft_is_approved(token_id.clone(), env::current_account_id(), Some(approval_id))
+    }
+
+    //internal method for locking a listing while an nft_transfer_payout for it is in flight. resolve_purchase
+    //removes it once the buyer has the token, or unlocks it again if the transfer failed
+    pub(crate) fn internal_lock_listing(&mut self, contract_and_token_id: &ContractAndTokenId) {
+        let mut listing = self.listings.get(contract_and_token_id).expect("No listing");
+        assert!(!listing.is_pending, "Purchase in progress");
+        listing.is_pending = true;
+        self.listings.insert(contract_and_token_id, &listing);
+    }
+
+    //internal method for selling a token to the account that made an offer on it. The owner has just approved the
+    //marketplace with `approval_id`, and the escrowed offer pays for the token through the usual purchase flow
+    pub(crate) fn internal_accept_offer(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        seller: AccountId,
+        approval_id: u64,
+        buyer_id: AccountId,
+    ) -> Promise {
+        assert!(seller != buyer_id, "Can't accept your own offer");
+        let offer = self
+            .internal_remove_offer(&nft_contract_id, &token_id, &buyer_id)
+            .expect("No offer from this account");
+        assert!(env::block_timestamp() < offer.expires_at, "Offer has expired");
+
+        EventLogVariant::OfferAccepted(OfferLog::new(&offer)).emit();
+        let marketplace_fee = self.internal_market_fee(&nft_contract_id, offer.amount);
+        self.internal_sell_with_approval(
+            Purchase {
+                nft_contract_id,
+                token_id,
+                seller,
+                buyer_id,
+                price: U128(offer.amount),
+                marketplace_fee: U128(marketplace_fee),
+                deposit: U128(offer.amount),
+                ft_token_id: None,
+            },
+            approval_id,
+        )
+    }
+
+    //internal method for filling one of `buyer_id`'s collection offers with a token the owner has just approved
+    //the marketplace for. The escrowed price of one token pays for it through the usual purchase flow
+    pub(crate) fn internal_fill_collection_offer(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        seller: AccountId,
+        approval_id: u64,
+        buyer_id: AccountId,
+    ) -> Promise {
+        assert!(seller != buyer_id, "Can't fill your own offer");
+        let mut offers = self
+            .collection_offers
+            .get(&nft_contract_id)
+            .expect("No collection offer from this account");
+        let mut offer = offers
+            .get(&buyer_id)
+            .expect("No collection offer from this account");
+
+        //the offer is taken down once its last token is bought
+        offer.quantity -= 1;
+        if offer.quantity > 0 {
+            offers.insert(&buyer_id, &offer);
+        } else {
+            self.internal_remove_collection_offer(&nft_contract_id, &buyer_id);
+        }
+
+        EventLogVariant::CollectionOfferFilled(CollectionOfferLog::new(&offer, Some(token_id.clone())))
+            .emit();
+        let marketplace_fee = self.internal_market_fee(&nft_contract_id, offer.price);
+        self.internal_sell_with_approval(
+            Purchase {
+                nft_contract_id,
+                token_id,
+                seller,
+                buyer_id,
+                price: U128(offer.price),
+                marketplace_fee: U128(marketplace_fee),
+                deposit: U128(offer.price),
+                ft_token_id: offer.ft_token_id,
+            },
+            approval_id,
+        )
+    }
+
+    //internal method for selling a token with the approval the owner has just given the marketplace. If the owner
+    //also listed the token, the listing is locked like any other purchase. Approving again replaces the listing's
+    //approval ID, so it's kept up to date in case the transfer fails and the listing stays up. A listing left over
+    //from a previous owner is taken down instead, so the new approval can't be used to sell it
+    pub(crate) fn internal_sell_with_approval(&mut self, purchase: Purchase, approval_id: u64) -> Promise {
+        self.assert_not_paused(PauseArea::Buying);
+        let contract_and_token_id =
+            format!("{}{}{}", purchase.nft_contract_id, DELIMETER, purchase.token_id);
+        //bidders in a sealed auction have committed to it, so the token can't be sold out from under them
+        assert!(
+            self.sealed_bids.get(&contract_and_token_id).is_none(),
+            "Sealed bids have been committed"
+        );
+        if let Some(mut listing) = self.listings.get(&contract_and_token_id) {
+            assert!(!listing.is_pending, "Purchase in progress");
+            assert!(listing.highest_bidder.is_none(), "Listing has an active bid");
+            if listing.seller != purchase.seller {
+                self.internal_remove_listing(
+                    purchase.nft_contract_id.clone(),
+                    purchase.token_id.clone(),
+                );
+            } else {
+                listing.approval_id = approval_id;
+                self.listings.insert(&contract_and_token_id, &listing);
+                self.internal_lock_listing(&contract_and_token_id);
+            }
+        }
+
+        self.process_purchase(purchase, approval_id)
+    }
+
+    //internal method for removing `buyer_id`'s offer on a token. The storage it took up goes back to the buyer,
+    //but the escrowed amount is left to the caller to refund or spend
+    pub(crate) fn internal_remove_offer(
+        &mut self,
+        nft_contract_id: &AccountId,
+        token_id: &TokenId,
+        buyer_id: &AccountId,
+    ) -> Option<Offer> {
+        let initial_storage_usage = env::storage_usage();
+
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        let mut offers = self.offers.get(&contract_and_token_id)?;
+        let offer = offers.remove(buyer_id)?;
+        //drop the token's map once its last offer is gone
+        if offers.is_empty() {
+            self.offers.remove(&contract_and_token_id);
+        } else {
+            self.offers.insert(&contract_and_token_id, &offers);
+        }
+
+        self.internal_release_storage(buyer_id, initial_storage_usage);
+        Some(offer)
+    }
+
+    //internal method for calculating the marketplace's cut of a sale price. owner_cut is in basis points
+    pub(crate) fn internal_market_fee(&self, nft_contract_id: &AccountId, price: u128) -> u128 {
+        //collections with their own fee pay that instead
+        let owner_cut = self.internal_collection_fee(nft_contract_id);
+        price
+            .saturating_mul(owner_cut.into())
+            .saturating_div(10000)
+    }
+
     //internal method for removing a listing from the market. This returns the previously removed listing object
     pub(crate) fn internal_remove_listing(
         &mut self,
         nft_contract_id: AccountId,
         token_id: TokenId,
     ) -> Listing {
+        //measure the storage so we can give the seller back what the listing was taking up
+        let initial_storage_usage = env::storage_usage();
+
         //get the unique listing ID (contract + DELIMITER + token ID)
         let contract_and_token_id = format!("{}{}{}", &nft_contract_id, DELIMETER, token_id);
         //get the listing object by removing the unique listing ID. If there was no listing, panic
@@ -52,7 +425,257 @@ impl Marketplace {
                 .insert(&nft_contract_id, &by_nft_contract_id);
         }
 
+        self.internal_release_storage(&listing.seller, initial_storage_usage);
+
         //return the listing object
         listing
     }
-}
\ No newline at end of file
+
+    //internal method for taking a listing down before it sells. Any bid held in escrow is refunded, and so is
+    //every sealed bid that could still have been revealed
+    pub(crate) fn internal_cancel_listing(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+    ) -> Listing {
+        let contract_and_token_id = format!("{}{}{}", &nft_contract_id, DELIMETER, token_id);
+        let listing = self.listings.get(&contract_and_token_id).expect("No listing");
+        assert!(!listing.is_pending, "Purchase in progress");
+        let listing = self.internal_remove_listing(nft_contract_id.clone(), token_id.clone());
+
+        //the highest bidder's deposit is still in escrow, so give it back
+        if let Some(highest_bidder) = listing.highest_bidder.clone() {
+            self.internal_transfer(&listing.ft_token_id, highest_bidder, listing.highest_price);
+        }
+
+        //a bid can only be forfeited once its bidder has had the whole reveal phase to reveal it. Cancelling the
+        //auction before then takes that chance away, so those bids are refunded whatever the policy says
+        let sealed_bids = self
+            .sealed_bids
+            .get(&contract_and_token_id)
+            .map(|sealed_bids| sealed_bids.to_vec())
+            .unwrap_or_default();
+        for (bidder_id, sealed_bid) in sealed_bids {
+            if env::block_timestamp() >= sealed_bid.reveal_end_at {
+                continue;
+            }
+            self.internal_remove_sealed_bid(&contract_and_token_id, &bidder_id);
+            self.internal_transfer(&None, bidder_id.clone(), sealed_bid.deposit);
+            EventLogVariant::UnrevealedBidReleased(SealedBidLog {
+                nft_contract_id: nft_contract_id.clone(),
+                token_id: token_id.clone(),
+                bidder_id,
+                deposit: U128(sealed_bid.deposit),
+                forfeited_to: None,
+            })
+            .emit();
+        }
+
+        EventLogVariant::ListingCancelled(ListingCancelledLog {
+            seller: listing.seller.clone(),
+            nft_contract_id: listing.nft_contract_id.parse().unwrap(),
+            token_id: listing.token_id.clone(),
+            refunded_bidder_id: listing.highest_bidder.clone(),
+        })
+        .emit();
+
+        listing
+    }
+
+    //internal method for charging an account for the storage used since `initial_storage_usage` was measured.
+    //Panics if the account's storage balance doesn't cover everything it's now using
+    pub(crate) fn internal_charge_storage(
+        &mut self,
+        account_id: &AccountId,
+        initial_storage_usage: StorageUsage,
+    ) {
+        let storage_used = self.storage_used.get(account_id).unwrap_or(0)
+            + env::storage_usage().saturating_sub(initial_storage_usage);
+        let storage_cost = Balance::from(storage_used) * env::storage_byte_cost();
+        let storage_paid = self.storage_deposits.get(account_id).unwrap_or(0);
+        assert!(
+            storage_paid >= storage_cost,
+            "Insufficient storage paid: {}, required {} for {} bytes",
+            storage_paid,
+            storage_cost,
+            storage_used
+        );
+        self.storage_used.insert(account_id, &storage_used);
+    }
+
+    //internal method for crediting an account with the storage freed since `initial_storage_usage` was measured
+    pub(crate) fn internal_release_storage(
+        &mut self,
+        account_id: &AccountId,
+        initial_storage_usage: StorageUsage,
+    ) {
+        if let Some(storage_used) = self.storage_used.get(account_id) {
+            let storage_freed = initial_storage_usage.saturating_sub(env::storage_usage());
+            self.storage_used
+                .insert(account_id, &storage_used.saturating_sub(storage_freed));
+        }
+    }
+
+    //internal method for adding `buyer_id`'s offer to buy up to `quantity` tokens of a collection for `price` each.
+    //The whole amount has already been received in NEAR or `ft_token_id`. An existing offer from the buyer on the
+    //same collection is replaced and refunded
+    pub(crate) fn internal_add_collection_offer(
+        &mut self,
+        nft_contract_id: AccountId,
+        buyer_id: AccountId,
+        price: Balance,
+        quantity: u32,
+        ft_token_id: Option<AccountId>,
+    ) {
+        self.assert_not_paused(PauseArea::Bidding);
+        assert!(price > 0, "Price must be greater than 0");
+        assert!(quantity > 0, "Quantity must be greater than 0");
+
+        //measure the storage the offer takes up so we can charge it to the buyer's storage balance
+        let initial_storage_usage = env::storage_usage();
+
+        let offer = CollectionOffer {
+            buyer_id: buyer_id.clone(),
+            nft_contract_id: nft_contract_id.clone(),
+            price,
+            quantity,
+            ft_token_id,
+        };
+        let mut offers = self.collection_offers.get(&nft_contract_id).unwrap_or_else(|| {
+            UnorderedMap::new(
+                StorageKey::CollectionOffersInner {
+                    //we get a new unique prefix for the collection by hashing the nft contract
+                    account_id_hash: hash_account_id(&nft_contract_id),
+                }
+                .try_to_vec()
+                .unwrap(),
+            )
+        });
+        let previous_offer = offers.insert(&buyer_id, &offer);
+        self.collection_offers.insert(&nft_contract_id, &offers);
+
+        self.internal_charge_storage(&buyer_id, initial_storage_usage);
+
+        //the replaced offer's tokens are still in escrow, so give them back
+        if let Some(previous_offer) = previous_offer {
+            self.internal_transfer(
+                &previous_offer.ft_token_id,
+                buyer_id,
+                previous_offer.total(),
+            );
+            EventLogVariant::CollectionOfferWithdrawn(CollectionOfferLog::new(&previous_offer, None))
+                .emit();
+        }
+        EventLogVariant::CollectionOfferMade(CollectionOfferLog::new(&offer, None)).emit();
+    }
+
+    //internal method for removing `buyer_id`'s collection offer. The storage it took up goes back to the buyer,
+    //but the escrowed amount is left to the caller to refund
+    pub(crate) fn internal_remove_collection_offer(
+        &mut self,
+        nft_contract_id: &AccountId,
+        buyer_id: &AccountId,
+    ) -> Option<CollectionOffer> {
+        let initial_storage_usage = env::storage_usage();
+
+        let mut offers = self.collection_offers.get(nft_contract_id)?;
+        let offer = offers.remove(buyer_id)?;
+        //drop the collection's map once its last offer is gone
+        if offers.is_empty() {
+            self.collection_offers.remove(nft_contract_id);
+        } else {
+            self.collection_offers.insert(nft_contract_id, &offers);
+        }
+
+        self.internal_release_storage(buyer_id, initial_storage_usage);
+        Some(offer)
+    }
+
+    //internal method for removing `bidder_id`'s sealed bid on a token. The storage it took up goes back to the
+    //bidder, but the escrowed deposit is left to the caller to refund or hand over
+    pub(crate) fn internal_remove_sealed_bid(
+        &mut self,
+        contract_and_token_id: &ContractAndTokenId,
+        bidder_id: &AccountId,
+    ) -> Option<SealedBid> {
+        let initial_storage_usage = env::storage_usage();
+
+        let mut sealed_bids = self.sealed_bids.get(contract_and_token_id)?;
+        let sealed_bid = sealed_bids.remove(bidder_id)?;
+        //drop the token's map once its last bid is gone
+        if sealed_bids.is_empty() {
+            self.sealed_bids.remove(contract_and_token_id);
+        } else {
+            self.sealed_bids.insert(contract_and_token_id, &sealed_bids);
+        }
+
+        self.internal_release_storage(bidder_id, initial_storage_usage);
+        Some(sealed_bid)
+    }
+
+    //internal method for adding a bid to a token's bid history. The bidder pays for the storage it takes up. Once
+    //the history is full the oldest bid is dropped, and its storage goes back to whoever placed it
+    pub(crate) fn internal_record_bid(
+        &mut self,
+        contract_and_token_id: &ContractAndTokenId,
+        bidder_id: &AccountId,
+        amount: Balance,
+    ) {
+        let mut bid_history = self
+            .bid_history
+            .get(contract_and_token_id)
+            .unwrap_or_else(|| BidHistory {
+                created_by: bidder_id.clone(),
+                first: 0,
+                len: 0,
+                bids: LookupMap::new(
+                    StorageKey::BidHistoryInner {
+                        //we get a new unique prefix for the collection by hashing the unique sale ID
+                        contract_and_token_id_hash: hash_contract_and_token_id(contract_and_token_id),
+                    }
+                    .try_to_vec()
+                    .unwrap(),
+                ),
+            });
+
+        if bid_history.len == MAX_BID_HISTORY {
+            let initial_storage_usage = env::storage_usage();
+            let oldest_bid = bid_history.bids.remove(&bid_history.first).unwrap();
+            self.internal_release_storage(&oldest_bid.bidder_id, initial_storage_usage);
+            bid_history.first += 1;
+            bid_history.len -= 1;
+        }
+
+        //measure the storage the bid takes up so we can charge it to the bidder's storage balance
+        let initial_storage_usage = env::storage_usage();
+        bid_history.bids.insert(
+            &(bid_history.first + bid_history.len),
+            &Bid {
+                bidder_id: bidder_id.clone(),
+                amount: U128(amount),
+                timestamp: U64(env::block_timestamp()),
+            },
+        );
+        bid_history.len += 1;
+        self.bid_history.insert(contract_and_token_id, &bid_history);
+        self.internal_charge_storage(bidder_id, initial_storage_usage);
+    }
+
+    //internal method for dropping a token's bid history, giving every bidder back the storage their bids used.
+    //Called when the token is listed again, since the old bids don't belong to the new listing
+    pub(crate) fn internal_clear_bid_history(&mut self, contract_and_token_id: &ContractAndTokenId) {
+        let mut bid_history = match self.bid_history.get(contract_and_token_id) {
+            Some(bid_history) => bid_history,
+            None => return,
+        };
+        for index in bid_history.first..bid_history.first + bid_history.len {
+            let initial_storage_usage = env::storage_usage();
+            let bid = bid_history.bids.remove(&index).unwrap();
+            self.internal_release_storage(&bid.bidder_id, initial_storage_usage);
+        }
+
+        let initial_storage_usage = env::storage_usage();
+        self.bid_history.remove(contract_and_token_id);
+        self.internal_release_storage(&bid_history.created_by, initial_storage_usage);
+    }
+}
@@ -1,28 +1,69 @@
-use external::ext_contract;
-use near_contract_standards::non_fungible_token::{NonFungibleToken, TokenId};
+use bid_history::{Bid, BidHistory};
+use collections::{CollectionConfig, CollectionMode};
+use external::{ext_contract, ext_ft, Payout};
+use fees::{PendingOwnerCut, MAX_OWNER_CUT};
+use nep297::*;
+use offers::{CollectionOffer, Offer};
+use owner::AdminRole;
+use pause::{PauseArea, Paused};
+use sealed_auction::{SealedAuction, SealedBid, UnrevealedBidPolicy};
+use near_contract_standards::non_fungible_token::TokenId;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::{LookupMap, UnorderedMap, UnorderedSet};
 use near_sdk::env::STORAGE_PRICE_PER_BYTE;
 use near_sdk::json_types::{U128, U64};
 use near_sdk::{
     assert_one_yocto, env, ext_contract, near_bindgen, promise_result_as_success, AccountId,
-    Balance, BorshStorageKey, CryptoHash, Gas, PanicOnDefault, Promise,
+    Balance, BorshStorageKey, CryptoHash, Gas, PanicOnDefault, Promise, PromiseOrValue,
+    StorageUsage,
 };
 use serde::{Deserialize, Serialize};
 
+mod bid_history;
+mod collections;
 mod external;
+mod fees;
+mod ft_callback;
+mod ft_claims;
 mod internal;
+mod nep297;
 mod nft_callback;
+mod offers;
+mod owner;
+mod pause;
 mod sale_views;
+mod sealed_auction;
+mod stale_listings;
+mod storage;
 
 #[cfg(test)]
 mod test;
 
-const GAS_FOR_RESOLVE_PURCHASE: Gas = Gas(115_000_000_000_000);
+//enough to pay out MAX_LEN_PAYOUT royalties, the fee and a refund, each with an ft_transfer and its callback
+const GAS_FOR_RESOLVE_PURCHASE: Gas = Gas(145_000_000_000_000);
 const GAS_FOR_NFT_TRANSFER: Gas = Gas(15_000_000_000_000);
+const GAS_FOR_FT_TRANSFER: Gas = Gas(5_000_000_000_000);
+const GAS_FOR_RESOLVE_FT_TRANSFER: Gas = Gas(5_000_000_000_000);
+const GAS_FOR_NFT_IS_APPROVED: Gas = Gas(10_000_000_000_000);
+//enough to kick off the purchase (nft_transfer_payout and resolve_purchase) once the approval checks out
+const GAS_FOR_RESOLVE_PURCHASE_APPROVAL: Gas = Gas(175_000_000_000_000);
+const GAS_FOR_RESOLVE_APPROVAL: Gas = Gas(20_000_000_000_000);
 
-//the minimum storage to have a sale on the contract.
-const STORAGE_PER_SALE: u128 = 1000 * STORAGE_PRICE_PER_BYTE;
+//the maximum amount of accounts the market can payout at once (this is limited by GAS)
+const MAX_LEN_PAYOUT: u32 = 10;
+
+//by default, a bid placed within 10 minutes of an auction's end pushes the end back by another 10 minutes
+const DEFAULT_AUCTION_EXTENSION_WINDOW: u64 = 10 * 60 * 1_000_000_000;
+const DEFAULT_AUCTION_EXTENSION_DURATION: u64 = 10 * 60 * 1_000_000_000;
+
+//what whoever removes a stale listing is paid out of the seller's storage balance
+const STALE_LISTING_REWARD: u128 = 100 * STORAGE_PRICE_PER_BYTE;
+
+//the most bids the history of a listing keeps. Once it's full, each new bid pushes out the oldest
+const MAX_BID_HISTORY: u64 = 50;
+
+//the minimum storage balance an account needs to register with the marketplace
+const MIN_STORAGE_BALANCE: u128 = 1000 * STORAGE_PRICE_PER_BYTE;
 
 //every sale will have a unique ID which is `CONTRACT + DELIMITER + TOKEN_ID`
 static DELIMETER: &str = ".";
@@ -40,11 +81,13 @@ pub struct Listing {
     pub nft_contract_id: String,
     //actual token ID for sale
     pub token_id: String,
-    //sale price in yoctoNEAR that the token is listed for
+    //sale price that the token is listed for, in yoctoNEAR or the smallest unit of ft_token_id
     pub starting_price: u128,
+    //fungible token the listing is priced in. None means it's priced in NEAR
+    pub ft_token_id: Option<AccountId>,
 
     pub started_at: u64,
-
+    //when an auction ends. A fixed price listing can't be bought from this point on, 0 means it never expires
     pub end_at: u64,
 
     pub highest_bidder: Option<AccountId>,
@@ -52,20 +95,199 @@ pub struct Listing {
     pub highest_price: u128,
 
     pub is_auction: bool,
+    //a bid placed within this many nanoseconds of end_at pushes end_at back by extension_duration. 0 turns it off
+    pub extension_window: u64,
+
+    pub extension_duration: u64,
+    //the lowest winning bid the seller will sell for. It's left out of the views so bidders can't aim for it,
+    //although like all contract state it can still be read from the chain. 0 means there's no reserve
+    #[serde(skip)]
+    pub reserve_price: u128,
+    //how much each bid has to raise the highest bid by
+    pub min_increment: MinIncrement,
+    //an auction can also be bought outright for this price, which ends it early. Fixed price listings don't use it
+    pub buy_now_price: Option<u128>,
+    //set for dutch auctions, whose price falls from starting_price to a floor between started_at and end_at.
+    //They're bought like fixed price listings, at whatever the price is at the time
+    pub dutch_auction: Option<DutchAuction>,
+    //set for sealed auctions, where bids are committed as hashes until end_at and then revealed. These are also
+    //auctions, but their bids go through commit_bid and reveal_bid instead of bid
+    pub sealed_auction: Option<SealedAuction>,
+    //set while an nft_transfer_payout for this listing is in flight. The listing is only dropped once it succeeds
+    pub is_pending: bool,
+}
+
+impl Listing {
+    //what the token can be bought for right now: the fixed price, a dutch auction's current price, or an auction's
+    //buy now price. None if it can only be won by bidding
+    pub(crate) fn current_price(&self) -> Option<u128> {
+        if self.is_auction {
+            return self.buy_now_price;
+        }
+        match self.dutch_auction.as_ref() {
+            Some(dutch_auction) => Some(dutch_auction.price_at(
+                self.starting_price,
+                self.started_at,
+                self.end_at,
+                env::block_timestamp(),
+            )),
+            None => Some(self.starting_price),
+        }
+    }
+
+    //whether a fixed price listing has passed its expiry. Auctions end instead, and a dutch auction keeps selling
+    //at its floor price
+    pub(crate) fn is_expired(&self) -> bool {
+        !self.is_auction
+            && self.dutch_auction.is_none()
+            && self.end_at != 0
+            && env::block_timestamp() >= self.end_at
+    }
+
+    //the price a buyer pays for the token right now
+    pub(crate) fn purchase_price(&self) -> u128 {
+        self.current_price()
+            .expect("Auctions are settled with settle_auction")
+    }
+}
+
+//how the price of a dutch auction falls between started_at and end_at
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(crate = "near_sdk::serde", tag = "type", rename_all = "snake_case")]
+pub enum PriceCurve {
+    //a little every nanosecond
+    Linear,
+    //in `steps` equal drops, the last one landing on the floor at end_at
+    Stepped { steps: u32 },
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DutchAuction {
+    //the lowest the price falls to. The token keeps selling at it once end_at has passed
+    pub floor_price: U128,
+    pub curve: PriceCurve,
+}
+
+impl DutchAuction {
+    //the price at `timestamp` for an auction falling from `starting_price` to the floor between `started_at` and `end_at`
+    pub(crate) fn price_at(
+        &self,
+        starting_price: u128,
+        started_at: u64,
+        end_at: u64,
+        timestamp: u64,
+    ) -> u128 {
+        let duration = u128::from(end_at - started_at);
+        let elapsed = u128::from(timestamp.clamp(started_at, end_at) - started_at);
+        let price_drop = starting_price - self.floor_price.0;
+
+        //how far along the drop the price is, as a fraction of `duration`
+        let elapsed = match self.curve {
+            PriceCurve::Linear => elapsed,
+            PriceCurve::Stepped { steps } => {
+                let steps = u128::from(steps);
+                elapsed * steps / duration * duration / steps
+            }
+        };
+        //price_drop * elapsed / duration, split up so multiplying by nanoseconds can't overflow
+        let dropped = price_drop / duration * elapsed + price_drop % duration * elapsed / duration;
+        starting_price - dropped
+    }
+}
+
+//how much a bid has to beat the highest bid by. Either a fixed amount, or basis points of the highest bid
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum MinIncrement {
+    Absolute(U128),
+    BasisPoints(u16),
+}
+
+impl Default for MinIncrement {
+    //any raise at all is a valid outbid
+    fn default() -> Self {
+        MinIncrement::Absolute(U128(1))
+    }
+}
+
+impl MinIncrement {
+    //the smallest bid that beats `highest_price`. A bid always has to be strictly higher
+    pub(crate) fn min_bid(&self, highest_price: u128) -> u128 {
+        let increment = match self {
+            MinIncrement::Absolute(amount) => amount.0,
+            MinIncrement::BasisPoints(basis_points) => highest_price
+                .saturating_mul((*basis_points).into())
+                .saturating_div(10000),
+        };
+        highest_price.saturating_add(increment.max(1))
+    }
 }
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct Marketplace {
     pub owner: AccountId,
+    //the marketplace's cut of every sale, in basis points. Capped at MAX_OWNER_CUT
     pub owner_cut: u16,
+    //an increase of owner_cut that only applies once its timelock has passed
+    pub pending_owner_cut: Option<PendingOwnerCut>,
+    //the account marketplace fees are paid to. It starts out as the owner
+    pub treasury_id: AccountId,
+    //fee and royalty terms agreed with individual nft contracts
+    pub collection_configs: UnorderedMap<AccountId, CollectionConfig>,
+    //which nft contracts can list: any of them, only allowlisted ones, or any that aren't blocklisted
+    pub collection_mode: CollectionMode,
+    pub allowlisted_collections: UnorderedSet<AccountId>,
+    pub blocklisted_collections: UnorderedSet<AccountId>,
+    //the parts of the marketplace admins have paused
+    pub paused: Paused,
+    //fungible tokens owed to accounts whose transfers failed, keyed by (account, fungible token)
+    pub ft_claims: LookupMap<(AccountId, AccountId), Balance>,
+    //the account the owner has proposed to hand the marketplace over to. It becomes the owner once it accepts
+    pub proposed_owner: Option<AccountId>,
+    //accounts the owner lets manage parts of the marketplace, and the roles they can do it with
+    pub admins: UnorderedMap<AccountId, Vec<AdminRole>>,
     pub listings: UnorderedMap<ContractAndTokenId, Listing>,
     //keep track of the storage that accounts have payed
     pub storage_deposits: LookupMap<AccountId, Balance>,
+    //keep track of the storage (in bytes) that each account's listings are taking up
+    pub storage_used: LookupMap<AccountId, StorageUsage>,
     //keep track of all the Sale IDs for every account ID
     pub by_owner_id: LookupMap<AccountId, UnorderedSet<ContractAndTokenId>>,
     //keep track of all the token IDs for sale for a given contract
     pub by_nft_contract_id: LookupMap<AccountId, UnorderedSet<TokenId>>,
+    //fungible tokens (besides NEAR) that listings can be priced in
+    pub ft_token_ids: UnorderedSet<AccountId>,
+    //keep track of the open offers on every token, keyed by the account that made them
+    pub offers: LookupMap<ContractAndTokenId, UnorderedMap<AccountId, Offer>>,
+    //keep track of the open offers on any token of an nft contract, keyed by the account that made them
+    pub collection_offers: LookupMap<AccountId, UnorderedMap<AccountId, CollectionOffer>>,
+    //the anti-sniping extension auctions get when the seller doesn't choose their own (in nanoseconds)
+    pub auction_extension_window: u64,
+    pub auction_extension_duration: u64,
+    //keep track of the sealed bids on every token that haven't been revealed yet, keyed by bidder
+    pub sealed_bids: LookupMap<ContractAndTokenId, UnorderedMap<AccountId, SealedBid>>,
+    //keep track of the most recent bids placed on every token that has been auctioned
+    pub bid_history: LookupMap<ContractAndTokenId, BidHistory>,
+}
+
+//everything resolve_purchase needs to pay out (or refund) a sale once nft_transfer_payout resolves
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Purchase {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub seller: AccountId,
+    pub buyer_id: AccountId,
+    //what the token sells for
+    pub price: U128,
+    //the marketplace's cut of the price, fixed when the sale starts so a fee change can't land halfway through
+    pub marketplace_fee: U128,
+    //what the buyer put up for it. Anything above the price is refunded
+    pub deposit: U128,
+    //the fungible token the buyer paid in. None means NEAR
+    pub ft_token_id: Option<AccountId>,
 }
 
 #[derive(BorshStorageKey, BorshSerialize)]
@@ -79,6 +301,20 @@ pub enum StorageKey {
     ByNFTTokenTypeInner { token_type_hash: CryptoHash },
     FTTokenIds,
     StorageDeposits,
+    StorageUsed,
+    Offers,
+    OffersInner { contract_and_token_id_hash: CryptoHash },
+    CollectionOffers,
+    CollectionOffersInner { account_id_hash: CryptoHash },
+    SealedBids,
+    SealedBidsInner { contract_and_token_id_hash: CryptoHash },
+    BidHistory,
+    BidHistoryInner { contract_and_token_id_hash: CryptoHash },
+    Admins,
+    CollectionConfigs,
+    AllowlistedCollections,
+    BlocklistedCollections,
+    FtClaims,
 }
 
 #[near_bindgen]
@@ -86,82 +322,66 @@ impl Marketplace {
     #[init]
     pub fn new(_owner_cut: u16) -> Self {
         assert!(!env::state_exists(), "Already initialized");
+        assert!(
+            _owner_cut <= MAX_OWNER_CUT,
+            "owner_cut can't be more than {}",
+            MAX_OWNER_CUT
+        );
         let owner_id = env::signer_account_id();
         Self {
-            owner: owner_id,
+            owner: owner_id.clone(),
             owner_cut: _owner_cut,
+            pending_owner_cut: None,
+            treasury_id: owner_id,
+            collection_configs: UnorderedMap::new(StorageKey::CollectionConfigs),
+            collection_mode: CollectionMode::Open,
+            allowlisted_collections: UnorderedSet::new(StorageKey::AllowlistedCollections),
+            blocklisted_collections: UnorderedSet::new(StorageKey::BlocklistedCollections),
+            paused: Paused::default(),
+            ft_claims: LookupMap::new(StorageKey::FtClaims),
+            proposed_owner: None,
+            admins: UnorderedMap::new(StorageKey::Admins),
             listings: UnorderedMap::new(StorageKey::Sales),
             by_owner_id: LookupMap::new(StorageKey::ByOwnerId),
             by_nft_contract_id: LookupMap::new(StorageKey::ByNFTContractId),
             storage_deposits: LookupMap::new(StorageKey::StorageDeposits),
+            storage_used: LookupMap::new(StorageKey::StorageUsed),
+            ft_token_ids: UnorderedSet::new(StorageKey::FTTokenIds),
+            offers: LookupMap::new(StorageKey::Offers),
+            collection_offers: LookupMap::new(StorageKey::CollectionOffers),
+            auction_extension_window: DEFAULT_AUCTION_EXTENSION_WINDOW,
+            auction_extension_duration: DEFAULT_AUCTION_EXTENSION_DURATION,
+            sealed_bids: LookupMap::new(StorageKey::SealedBids),
+            bid_history: LookupMap::new(StorageKey::BidHistory),
         }
     }
 
-    //Allows users to deposit storage. This is to cover the cost of storing sale objects on the contract
-    //Optional account ID is to users can pay for storage for other people.
-    #[payable]
-    pub fn storage_deposit(&mut self, account_id: Option<AccountId>) {
-        //get the account ID to pay for storage for
-        let storage_account_id = account_id
-            //convert the valid account ID into an account ID
-            .map(|a| a.into())
-            //if we didn't specify an account ID, we simply use the caller of the function
-            .unwrap_or_else(env::predecessor_account_id);
-
-        //get the deposit value which is how much the user wants to add to their storage
-        let deposit = env::attached_deposit();
-
-        //make sure the deposit is greater than or equal to the minimum storage for a sale
-        assert!(
-            deposit >= STORAGE_PER_SALE,
-            "Requires minimum deposit of {}",
-            STORAGE_PER_SALE
-        );
-
-        //get the balance of the account (if the account isn't in the map we default to a balance of 0)
-        let mut balance: u128 = self.storage_deposits.get(&storage_account_id).unwrap_or(0);
-        //add the deposit to their balance
-        balance += deposit;
-        //insert the balance back into the map for that account ID
-        self.storage_deposits.insert(&storage_account_id, &balance);
+    //Adds fungible tokens that sellers can price their listings in. Only the owner or an allowlist admin can do this
+    pub fn add_ft_token_ids(&mut self, ft_token_ids: Vec<AccountId>) {
+        self.assert_role(AdminRole::Allowlist);
+        for ft_token_id in ft_token_ids {
+            self.ft_token_ids.insert(&ft_token_id);
+        }
     }
 
-    //Allows users to withdraw any excess storage that they're not using. Say Bob pays 0.01N for 1 sale
-    //Alice then buys Bob's token. This means bob has paid 0.01N for a sale that's no longer on the marketplace
-    //Bob could then withdraw this 0.01N back into his account.
-    #[payable]
-    pub fn storage_withdraw(&mut self) {
-        //make sure the user attaches exactly 1 yoctoNEAR for security purposes.
-        //this will redirect them to the NEAR wallet (or requires a full access key).
-        assert_one_yocto();
-
-        //the account to withdraw storage to is always the function caller
-        let owner_id = env::predecessor_account_id();
-        //get the amount that the user has by removing them from the map. If they're not in the map, default to 0
-        let mut amount = self.storage_deposits.remove(&owner_id).unwrap_or(0);
-
-        //how many sales is that user taking up currently. This returns a set
-        let sales = self.by_owner_id.get(&owner_id);
-        //get the length of that set.
-        let len = sales.map(|s| s.len()).unwrap_or_default();
-        //how much NEAR is being used up for all the current sales on the account
-        let diff = u128::from(len) * STORAGE_PER_SALE;
-
-        //the excess to withdraw is the total storage paid - storage being used up.
-        amount -= diff;
-
-        //if that excess to withdraw is > 0, we transfer the amount to the user.
-        if amount > 0 {
-            Promise::new(owner_id.clone()).transfer(amount);
-        }
-        //we need to add back the storage being used up into the map if it's greater than 0.
-        //this is so that if the user had 500 sales on the market, we insert that value here so
-        //if those sales get taken down, the user can then go and withdraw 500 sales worth of storage.
-        if diff > 0 {
-            self.storage_deposits.insert(&owner_id, &diff);
+    //Stops accepting the given fungible tokens for new listings. Existing listings can still sell
+    pub fn remove_ft_token_ids(&mut self, ft_token_ids: Vec<AccountId>) {
+        self.assert_role(AdminRole::Allowlist);
+        for ft_token_id in ft_token_ids {
+            self.ft_token_ids.remove(&ft_token_id);
         }
     }
 
+    //Sets the anti-sniping extension auctions get by default: a bid placed within `window` nanoseconds of the
+    //auction's end pushes it back by `duration`. A window of 0 turns it off. Only the owner can do this
+    pub fn set_auction_extension(&mut self, window: U64, duration: U64) {
+        self.assert_owner();
+        self.auction_extension_window = window.0;
+        self.auction_extension_duration = duration.0;
+    }
+
+    //Changes the terms of a listing that was created through nft_approve. Only the owner that approved the
+    //marketplace can do this, and not while the listing is being bought or holds a bid
     pub fn create_listing(
         &mut self,
         _nft_address: AccountId,
@@ -170,150 +390,246 @@ impl Marketplace {
         _starting_price: u128,
         _end_at: u64,
         _started_at: u64,
-        _highest_price: u128,
         _is_auction: bool,
     ) {
-        let seller = env::signer_account_id();
+        self.assert_not_paused(PauseArea::Listing);
+        self.assert_can_list(&_nft_address);
+        let seller = env::predecessor_account_id();
         let contract_and_token_id = format!("{}{}{}", _nft_address, DELIMETER, _token_id);
+        let mut listing = self
+            .listings
+            .get(&contract_and_token_id)
+            .expect("NFT not approved yet");
+        assert!(seller == listing.seller, "Not authorized");
+        assert!(!listing.is_pending, "Purchase in progress");
+        assert!(listing.highest_bidder.is_none(), "Listing has an active bid");
         assert!(
-            self.listings.get(&contract_and_token_id) != None,
-            "NFT not approved yet"
+            self.sealed_bids.get(&contract_and_token_id).is_none(),
+            "Sealed bids have been committed"
         );
-        let mut listing = self.listings.get(&contract_and_token_id).unwrap();
+        assert!(_starting_price > 0, "Price must be greater than 0");
+
+        //a fixed price listing doesn't use the auction window, but end_at is when it expires (0 for never).
+        //Auctions get the default anti-sniping extension, like the ones created through nft_approve
+        let (started_at, end_at, extension_window, extension_duration) = if _is_auction {
+            assert!(
+                _started_at >= env::block_timestamp(),
+                "started_at can't be in the past"
+            );
+            internal::assert_valid_auction_window(_started_at, _end_at);
+            (
+                _started_at,
+                _end_at,
+                self.auction_extension_window,
+                self.auction_extension_duration,
+            )
+        } else {
+            internal::assert_valid_expiry(_end_at);
+            (0, _end_at, 0, 0)
+        };
 
-        listing.seller = seller;
         listing.starting_price = _starting_price;
-        listing.end_at = _end_at;
-        listing.started_at = _started_at;
+        listing.end_at = end_at;
+        listing.started_at = started_at;
         listing.is_auction = _is_auction;
+        listing.extension_window = extension_window;
+        listing.extension_duration = extension_duration;
+        //the new terms replace whatever kind of auction the listing was, along with its bids and the terms that
+        //went with them, which could otherwise be left below the new price
+        listing.reserve_price = 0;
+        listing.min_increment = MinIncrement::default();
+        listing.buy_now_price = None;
+        listing.dutch_auction = None;
+        listing.sealed_auction = None;
+        self.internal_clear_bid_history(&contract_and_token_id);
 
         self.listings.insert(&contract_and_token_id, &listing);
+        EventLogVariant::ListingUpdated(ListingLog::new(&listing)).emit();
     }
 
+    //Places a bid on an auction. The bid amount is the attached deposit, which the contract holds in escrow
+    //until the bidder is either outbid (and refunded) or wins the auction.
     #[payable]
-    pub fn bid(&mut self, _nft_address: AccountId, _token_id: String, _price: u128) {
-        assert_one_yocto();
-        let signer = env::signer_account_id();
+    pub fn bid(&mut self, _nft_address: AccountId, _token_id: String) {
+        //the escrowed funds come from the predecessor, so that's who we refund if they're outbid
+        let bidder = env::predecessor_account_id();
+        let price = env::attached_deposit();
 
-        let contract_and_token_id = format!("{}{}{}", _nft_address, DELIMETER, _token_id);
-        assert!(
-            self.listings.get(&contract_and_token_id) != None,
-            "NFT not listed yet"
-        );
-        let mut listing = self.listings.get(&contract_and_token_id).unwrap();
-        assert!(listing.is_auction == true, "Not auction");
-        assert!(
-            Self::is_on_auction(listing.clone()) == true,
-            "Auction not on"
-        );
-        assert!(listing.seller != signer, "Invalid bid");
-        assert!(_price > listing.highest_price, "Invalid price");
-        listing.highest_price = _price;
-        listing.highest_bidder = Some(signer);
+        self.internal_place_bid(_nft_address, _token_id, bidder, price, None);
     }
 
     pub fn cancel_listing(&mut self, _nft_address: AccountId, _token_id: String) {
-        let signer = env::signer_account_id();
+        //only the seller can take the listing down, not a contract they happen to be calling
+        let seller = env::predecessor_account_id();
 
         let contract_and_token_id = format!("{}{}{}", _nft_address, DELIMETER, _token_id);
+        let listing = self
+            .listings
+            .get(&contract_and_token_id)
+            .expect("NFT not listed yet");
+        assert!(seller == listing.seller, "Not authorized");
+        //bidders in a sealed auction have committed to it, so it has to run its course
         assert!(
-            self.listings.get(&contract_and_token_id) != None,
-            "NFT not listed yet"
+            self.sealed_bids.get(&contract_and_token_id).is_none(),
+            "Sealed bids have been committed"
         );
-        let listing = self.listings.get(&contract_and_token_id).unwrap();
-        assert!(signer == listing.seller, "Not authorized");
-        self.listings.remove(&contract_and_token_id);
+        self.internal_cancel_listing(_nft_address, _token_id);
     }
 
     #[payable]
     pub fn purchase_nft(&mut self, _nft_address: AccountId, _token_id: String) {
-        let signer = env::signer_account_id();
+        //the deposit comes from the predecessor, so that's who gets the token (and any refund)
+        let buyer_id = env::predecessor_account_id();
         let deposit = env::attached_deposit();
 
         let contract_and_token_id = format!("{}{}{}", _nft_address, DELIMETER, _token_id);
-        assert!(
-            self.listings.get(&contract_and_token_id) != None,
-            "NFT not listed yet"
-        );
-        let listing = self.listings.get(&contract_and_token_id).unwrap();
-        if listing.is_auction == true {
-            assert!(
-                Self::is_on_auction(listing.clone()) == true && listing.highest_price > 0,
-                "Auction not on"
-            );
-            assert!(listing.highest_bidder.unwrap() == signer, "not winner");
-            assert!(listing.highest_price <= deposit);
-        } else {
-            assert!(listing.starting_price <= deposit);
-        }
+        let listing = self
+            .listings
+            .get(&contract_and_token_id)
+            .expect("NFT not listed yet");
+        assert!(listing.ft_token_id.is_none(), "Listing is not priced in NEAR");
 
-        self.process_purchase(
-            _nft_address,
-            _token_id,
-            U128(deposit),
-            listing.seller,
-            signer,
-        );
+        self.internal_purchase(listing, buyer_id, deposit);
+    }
+
+    //Closes an auction once `end_at` (or a sealed auction's reveal_end_at) has passed. Anyone can call this: the
+    //winning bid is already held in escrow, so it pays for the token through the usual purchase flow. If nobody
+    //bid, the listing is just removed.
+    pub fn settle_auction(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: String,
+    ) -> PromiseOrValue<U128> {
+        self.assert_not_paused(PauseArea::Settlement);
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        let listing = self
+            .listings
+            .get(&contract_and_token_id)
+            .expect("NFT not listed yet");
+        assert!(listing.is_auction, "Not auction");
+        //a sealed auction only ends once the bids have been revealed
+        let ended_at = listing
+            .sealed_auction
+            .as_ref()
+            .map_or(listing.end_at, |sealed_auction| sealed_auction.reveal_end_at);
+        assert!(env::block_timestamp() >= ended_at, "Auction not ended");
+
+        //a vickrey auction's winner pays the runner up's bid, as long as it meets the reserve and starting price
+        let price = match listing.sealed_auction.as_ref() {
+            Some(sealed_auction) if sealed_auction.vickrey => sealed_auction
+                .second_price
+                .max(listing.reserve_price)
+                .max(listing.starting_price)
+                .min(listing.highest_price),
+            _ => listing.highest_price,
+        };
+
+        match listing.highest_bidder {
+            //the seller doesn't have to sell below the reserve, so the auction ends as if nobody bid and the top
+            //bid is refunded
+            Some(_) if listing.highest_price < listing.reserve_price => {
+                self.internal_cancel_listing(nft_contract_id, token_id);
+                PromiseOrValue::Value(U128(0))
+            }
+            Some(highest_bidder) => {
+                self.internal_lock_listing(&contract_and_token_id);
+                let marketplace_fee = self.internal_market_fee(&nft_contract_id, price);
+                PromiseOrValue::Promise(self.process_purchase(
+                    Purchase {
+                        nft_contract_id,
+                        token_id,
+                        seller: listing.seller,
+                        buyer_id: highest_bidder,
+                        price: U128(price),
+                        marketplace_fee: U128(marketplace_fee),
+                        deposit: U128(listing.highest_price),
+                        ft_token_id: listing.ft_token_id,
+                    },
+                    listing.approval_id,
+                ))
+            }
+            None => {
+                self.internal_cancel_listing(nft_contract_id, token_id);
+                PromiseOrValue::Value(U128(0))
+            }
+        }
     }
 
     pub fn set_price(&mut self, _nft_address: AccountId, _token_id: String, _price: u128) {
-        let signer = env::signer_account_id();
+        self.assert_not_paused(PauseArea::Listing);
+        let seller = env::predecessor_account_id();
+        assert!(_price > 0, "Price must be greater than 0");
 
         let contract_and_token_id = format!("{}{}{}", _nft_address, DELIMETER, _token_id);
-        assert!(
-            self.listings.get(&contract_and_token_id) != None,
-            "NFT not listed yet"
-        );
-        let mut listing = self.listings.get(&contract_and_token_id).unwrap();
-        assert!(listing.is_auction == false, "is auction");
-        assert!(signer == listing.seller, "Not authorized");
+        let mut listing = self
+            .listings
+            .get(&contract_and_token_id)
+            .expect("NFT not listed yet");
+        assert!(!listing.is_auction, "is auction");
+        assert!(listing.dutch_auction.is_none(), "is dutch auction");
+        assert!(!listing.is_pending, "Purchase in progress");
+        assert!(seller == listing.seller, "Not authorized");
+        let old_price = listing.starting_price;
         listing.starting_price = _price;
 
         self.listings.insert(&contract_and_token_id, &listing);
-    }
-
-    pub fn storage_minimum_balance(&self) -> U128 {
-        U128(STORAGE_PER_SALE)
-    }
-
-    pub fn storage_balance_of(&self, account_id: AccountId) -> U128 {
-        U128(self.storage_deposits.get(&account_id).unwrap_or(0))
+        EventLogVariant::PriceChanged(PriceChangedLog {
+            nft_contract_id: _nft_address,
+            token_id: _token_id,
+            old_price: U128(old_price),
+            new_price: U128(_price),
+        })
+        .emit();
     }
 
     fn is_on_auction(listing: Listing) -> bool {
-        return env::block_timestamp() > listing.started_at
-            && env::block_timestamp() < listing.end_at;
+        env::block_timestamp() > listing.started_at && env::block_timestamp() < listing.end_at
     }
 
+    //Transfers the token to the buyer using `approval_id` and pays out the sale once the transfer resolves.
+    //Callers lock the token's listing (if there is one) beforehand so nothing else can sell it in the meantime
     #[private]
-    pub fn process_purchase(
-        &mut self,
-        nft_contract_id: AccountId,
-        token_id: String,
-        price: U128,
-        seller: AccountId,
-        buyer: AccountId,
-    ) -> Promise {
-        //get the sale object by removing the sale
-        let sale =
-            self.internal_remove_listing(nft_contract_id.clone(), token_id.to_string().clone());
+    pub fn process_purchase(&mut self, purchase: Purchase, approval_id: u64) -> Promise {
+        //contracts that don't implement nft_transfer_payout get a plain transfer, and resolve_purchase pays out the
+        //royalties we have on file for them instead
+        let has_fallback_royalties = self
+            .collection_configs
+            .get(&purchase.nft_contract_id)
+            .is_some_and(|config| config.fallback_royalties.is_some());
+        if has_fallback_royalties {
+            return ext_contract::ext(purchase.nft_contract_id.clone())
+                .with_attached_deposit(1)
+                .with_static_gas(GAS_FOR_NFT_TRANSFER)
+                .nft_transfer(
+                    purchase.buyer_id.clone(),
+                    purchase.token_id.clone(),
+                    Some(approval_id),
+                    Some("payout from market".to_string()),
+                )
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(GAS_FOR_RESOLVE_PURCHASE)
+                        .resolve_purchase(purchase),
+                );
+        }
 
         //a payout object used for the market to distribute funds to the appropriate accounts.
-        ext_contract::ext(nft_contract_id)
+        ext_contract::ext(purchase.nft_contract_id.clone())
             // Attach 1 yoctoNEAR with static GAS equal to the GAS for nft transfer. Also attach an unused GAS weight of 1 by default.
             .with_attached_deposit(1)
             .with_static_gas(GAS_FOR_NFT_TRANSFER)
             .nft_transfer_payout(
-                buyer.clone(),                    //purchaser (person to transfer the NFT to)
-                token_id.to_string(),             //token ID to transfer
-                sale.approval_id, //market contract's approval ID in order to transfer the token on behalf of the owner
+                purchase.buyer_id.clone(),        //purchaser (person to transfer the NFT to)
+                purchase.token_id.clone(),        //token ID to transfer
+                approval_id, //market contract's approval ID in order to transfer the token on behalf of the owner
                 "payout from market".to_string(), //memo (to include some context)
                 /*
                     the price that the token was purchased for. This will be used in conjunction with the royalty percentages
                     for the token in order to determine how much money should go to which account.
                 */
-                price,
-                10, //the maximum amount of accounts the market can payout at once (this is limited by GAS)
+                //the marketplace fee is taken off the top, royalties are split from what's left
+                U128(purchase.price.0 - purchase.marketplace_fee.0),
+                MAX_LEN_PAYOUT,
             )
             //after the transfer payout has been initiated, we resolve the promise by calling our own resolve_purchase function.
             //resolve purchase will take the payout object returned from the nft_transfer_payout and actually pay the accounts
@@ -321,30 +637,144 @@ impl Marketplace {
                 // No attached deposit with static GAS equal to the GAS for resolving the purchase. Also attach an unused GAS weight of 1 by default.
                 Self::ext(env::current_account_id())
                     .with_static_gas(GAS_FOR_RESOLVE_PURCHASE)
-                    .resolve_purchase(seller, price.into()),
+                    .resolve_purchase(purchase),
             )
     }
 
+    //Called back with the result of the nft_is_approved check internal_purchase makes before a purchase. If the
+    //marketplace lost its approval the listing is dead, so it's removed and the buyer refunded. Otherwise the
+    //purchase goes ahead. The listing stays locked in between, so nothing else can change it
     #[private]
-    pub fn resolve_purchase(&mut self, seller: AccountId, price: u128) -> u128 {
-        let owner_cut = price
-            .saturating_mul(self.owner_cut.into())
-            .saturating_div(10000);
+    pub fn resolve_purchase_approval(
+        &mut self,
+        purchase: Purchase,
+        approval_id: u64,
+    ) -> PromiseOrValue<U128> {
+        //only a definite "no" counts. If the check itself failed, the transfer will tell us soon enough
+        if internal::promise_result_as_approval() != Some(false) {
+            return PromiseOrValue::Promise(self.process_purchase(purchase, approval_id));
+        }
 
-        // NEAR payouts
-        Promise::new(seller).transfer(price.saturating_sub(owner_cut));
-        Promise::new(self.owner.clone()).transfer(owner_cut);
+        self.internal_remove_listing(purchase.nft_contract_id.clone(), purchase.token_id.clone());
+        self.internal_transfer(&purchase.ft_token_id, purchase.buyer_id.clone(), purchase.deposit.0);
+        EventLogVariant::SaleFailed(SaleFailedLog {
+            buyer_id: purchase.buyer_id,
+            nft_contract_id: purchase.nft_contract_id,
+            token_id: purchase.token_id,
+            refund: purchase.deposit,
+            ft_token_id: purchase.ft_token_id,
+        })
+        .emit();
+        PromiseOrValue::Value(U128(0))
+    }
+
+    #[private]
+    pub fn resolve_purchase(&mut self, purchase: Purchase) -> U128 {
+        let Purchase {
+            nft_contract_id,
+            token_id,
+            seller,
+            buyer_id,
+            price,
+            marketplace_fee,
+            deposit,
+            ft_token_id,
+        } = purchase;
+        let (price, deposit) = (price.0, deposit.0);
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+
+        //if the transfer didn't go through, the buyer still owns their money and the seller still owns the token
+        let payout_result = match promise_result_as_success() {
+            Some(value) => value,
+            None => {
+                //unlock the listing so it can be bought again. The buyer's funds (or an auction's winning bid)
+                //are returned, so the auction no longer has a highest bidder
+                if let Some(mut listing) = self.listings.get(&contract_and_token_id) {
+                    listing.is_pending = false;
+                    if listing.highest_bidder.as_ref() == Some(&buyer_id) {
+                        listing.highest_bidder = None;
+                        listing.highest_price = 0;
+                    }
+                    self.listings.insert(&contract_and_token_id, &listing);
+                }
+                self.internal_transfer(&ft_token_id, buyer_id.clone(), deposit);
+                EventLogVariant::SaleFailed(SaleFailedLog {
+                    buyer_id,
+                    nft_contract_id,
+                    token_id,
+                    refund: U128(deposit),
+                    ft_token_id,
+                })
+                .emit();
+                return U128(0);
+            }
+        };
+
+        //the token is now with the buyer so the sale is over
+        if self.listings.get(&contract_and_token_id).is_some() {
+            self.internal_remove_listing(nft_contract_id.clone(), token_id.clone());
+        }
+
+        let owner_cut = marketplace_fee.0;
+        let payout_balance = price - owner_cut;
+
+        //the token has moved, so the seller gets paid no matter what. We only honor the royalties if the payout
+        //object is well formed and doesn't try to pay out more than the balance we passed to nft_transfer_payout.
+        //Collections with fallback royalties were sold with nft_transfer, so their royalties come from their config
+        let fallback_payout = self
+            .collection_configs
+            .get(&nft_contract_id)
+            .and_then(|config| config.fallback_payout(payout_balance));
+        let mut payout = fallback_payout
+            .map(|payout| Payout { payout })
+            .or_else(|| near_sdk::serde_json::from_slice::<Payout>(&payout_result).ok())
+            .filter(|payout| payout.payout.len() <= MAX_LEN_PAYOUT as usize)
+            .filter(|payout| {
+                payout
+                    .payout
+                    .values()
+                    .try_fold(0u128, |total, amount| total.checked_add(amount.0))
+                    .is_some_and(|total| total <= payout_balance)
+            })
+            .map(|payout| payout.payout)
+            .unwrap_or_default();
+
+        //whatever the payout didn't account for belongs to the seller
+        let remainder = payout_balance - payout.values().map(|amount| amount.0).sum::<u128>();
+        if remainder > 0 {
+            let seller_amount = payout.entry(seller.clone()).or_insert(U128(0));
+            seller_amount.0 += remainder;
+        }
+
+        // NEAR or FT payouts
+        for (receiver_id, amount) in payout.iter() {
+            if amount.0 > 0 {
+                self.internal_transfer(&ft_token_id, receiver_id.clone(), amount.0);
+            }
+        }
+        if owner_cut > 0 {
+            self.internal_transfer(&ft_token_id, self.treasury_id.clone(), owner_cut);
+        }
+
+        //give back whatever the buyer attached on top of the price
+        let refund = deposit - price;
+        if refund > 0 {
+            self.internal_transfer(&ft_token_id, buyer_id.clone(), refund);
+        }
+        EventLogVariant::SaleCompleted(SaleCompletedLog {
+            seller,
+            buyer_id,
+            nft_contract_id,
+            token_id,
+            price: U128(price),
+            marketplace_fee: U128(owner_cut),
+            payout,
+            refund: U128(refund),
+            ft_token_id,
+        })
+        .emit();
 
         //return the price payout out
-        price
+        U128(price)
     }
 }
-
-#[ext_contract(ext_self)]
-trait ExtSelf {
-    fn resolve_purchase(
-        &mut self,
-        buyer_id: AccountId,
-        price: U128,
-    ) -> Promise;
-}
\ No newline at end of file
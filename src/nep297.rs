@@ -0,0 +1,358 @@
+use crate::*;
+use std::collections::HashMap;
+use std::fmt;
+
+// NEP-297 event logs
+
+//every event is logged as `EVENT_JSON:{"standard":"nft_marketplace","version":"1.0.0","event":...,"data":...}`.
+//bump the version whenever the shape of an event changes so indexers know how to parse it
+pub const EVENT_STANDARD: &str = "nft_marketplace";
+pub const EVENT_VERSION: &str = "1.11.0";
+
+//the top level event log. `event` and `data` come from the flattened variant
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EventLog {
+    pub standard: String,
+    pub version: String,
+
+    #[serde(flatten)]
+    pub event: EventLogVariant,
+}
+
+impl fmt::Display for EventLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "EVENT_JSON:{}",
+            &near_sdk::serde_json::to_string(self).map_err(|_| fmt::Error)?
+        ))
+    }
+}
+
+//every action the marketplace logs. The variant name becomes the `event` and its contents the `data`
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde", tag = "event", content = "data", rename_all = "snake_case")]
+pub enum EventLogVariant {
+    ListingCreated(ListingLog),
+    ListingUpdated(ListingLog),
+    PriceChanged(PriceChangedLog),
+    BidPlaced(BidLog),
+    OutbidRefund(BidLog),
+    ListingCancelled(ListingCancelledLog),
+    SaleCompleted(SaleCompletedLog),
+    SaleFailed(SaleFailedLog),
+    StorageDeposit(StorageLog),
+    StorageWithdraw(StorageLog),
+    OfferMade(OfferLog),
+    OfferWithdrawn(OfferLog),
+    OfferExpired(OfferLog),
+    OfferAccepted(OfferLog),
+    CollectionOfferMade(CollectionOfferLog),
+    CollectionOfferWithdrawn(CollectionOfferLog),
+    CollectionOfferFilled(CollectionOfferLog),
+    BidCommitted(SealedBidLog),
+    BidRevealed(BidLog),
+    UnrevealedBidReleased(SealedBidLog),
+    StaleListingRemoved(StaleListingLog),
+    OwnerProposed(OwnerLog),
+    OwnerTransferred(OwnerLog),
+    RoleGranted(RoleLog),
+    RoleRevoked(RoleLog),
+    OwnerCutChanged(OwnerCutLog),
+    TreasuryChanged(TreasuryLog),
+    CollectionConfigSet(CollectionConfigLog),
+    CollectionConfigRemoved(CollectionConfigLog),
+    PauseToggled(PauseLog),
+    CollectionModeChanged(CollectionModeLog),
+    CollectionsAllowlisted(CollectionListLog),
+    CollectionsBlocklisted(CollectionListLog),
+    FtTransferFailed(FtClaimLog),
+    FtClaimed(FtClaimLog),
+}
+
+impl EventLogVariant {
+    //logs the event with the marketplace's standard and version
+    pub(crate) fn emit(self) {
+        let log = EventLog {
+            standard: EVENT_STANDARD.to_string(),
+            version: EVENT_VERSION.to_string(),
+            event: self,
+        };
+        env::log_str(&log.to_string());
+    }
+}
+
+//the terms of a listing when it's created or its terms change
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ListingLog {
+    pub seller: AccountId,
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub approval_id: u64,
+    pub starting_price: U128,
+    pub started_at: U64,
+    pub end_at: U64,
+    pub is_auction: bool,
+    pub extension_window: U64,
+    pub extension_duration: U64,
+    //what an auction can be bought outright for. Left out when it can't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buy_now_price: Option<U128>,
+    //how a dutch auction's price falls from starting_price. Left out for other listings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dutch_auction: Option<DutchAuction>,
+    //the reveal phase and policies of a sealed auction. Left out for other listings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sealed_auction: Option<SealedAuction>,
+    //the fungible token the listing is priced in. Left out when they're in NEAR
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ft_token_id: Option<AccountId>,
+}
+
+impl ListingLog {
+    pub(crate) fn new(listing: &Listing) -> Self {
+        Self {
+            seller: listing.seller.clone(),
+            nft_contract_id: listing.nft_contract_id.parse().unwrap(),
+            token_id: listing.token_id.clone(),
+            approval_id: listing.approval_id,
+            starting_price: U128(listing.starting_price),
+            started_at: U64(listing.started_at),
+            end_at: U64(listing.end_at),
+            is_auction: listing.is_auction,
+            extension_window: U64(listing.extension_window),
+            extension_duration: U64(listing.extension_duration),
+            buy_now_price: listing.buy_now_price.map(U128),
+            dutch_auction: listing.dutch_auction.clone(),
+            sealed_auction: listing.sealed_auction.clone(),
+            ft_token_id: listing.ft_token_id.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceChangedLog {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub old_price: U128,
+    pub new_price: U128,
+}
+
+//a bid that was placed, or an escrowed bid that was refunded because it was outbid
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BidLog {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub bidder_id: AccountId,
+    pub amount: U128,
+    //when the auction ends, after any extension the bid caused
+    pub end_at: U64,
+    //the fungible token the amounts are in. Left out when they're in NEAR
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ft_token_id: Option<AccountId>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ListingCancelledLog {
+    pub seller: AccountId,
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    //the bidder whose escrowed bid was refunded, if there was one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refunded_bidder_id: Option<AccountId>,
+}
+
+//a completed sale. `payout` is everything the price was split into besides the marketplace fee
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SaleCompletedLog {
+    pub seller: AccountId,
+    pub buyer_id: AccountId,
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub price: U128,
+    pub marketplace_fee: U128,
+    pub payout: HashMap<AccountId, U128>,
+    //whatever the buyer attached on top of the price
+    pub refund: U128,
+    //the fungible token the amounts are in. Left out when they're in NEAR
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ft_token_id: Option<AccountId>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SaleFailedLog {
+    pub buyer_id: AccountId,
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub refund: U128,
+    //the fungible token the amounts are in. Left out when they're in NEAR
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ft_token_id: Option<AccountId>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageLog {
+    pub account_id: AccountId,
+    pub amount: U128,
+    //the account's total storage balance afterwards
+    pub total: U128,
+}
+
+//an offer on a token when it's made, and when it's withdrawn, refunded after expiring or accepted
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OfferLog {
+    pub buyer_id: AccountId,
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub amount: U128,
+    pub expires_at: U64,
+}
+
+impl OfferLog {
+    pub(crate) fn new(offer: &Offer) -> Self {
+        Self {
+            buyer_id: offer.buyer_id.clone(),
+            nft_contract_id: offer.nft_contract_id.clone(),
+            token_id: offer.token_id.clone(),
+            amount: U128(offer.amount),
+            expires_at: U64(offer.expires_at),
+        }
+    }
+}
+
+//a collection offer when it's made or withdrawn, and each time one of its tokens is bought
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionOfferLog {
+    pub buyer_id: AccountId,
+    pub nft_contract_id: AccountId,
+    pub price: U128,
+    //how many more tokens the offer will buy afterwards
+    pub quantity: u32,
+    //the token that was sold to the offer. Only set when it's filled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_id: Option<TokenId>,
+    //the fungible token the amounts are in. Left out when they're in NEAR
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ft_token_id: Option<AccountId>,
+}
+
+impl CollectionOfferLog {
+    pub(crate) fn new(offer: &CollectionOffer, token_id: Option<TokenId>) -> Self {
+        Self {
+            buyer_id: offer.buyer_id.clone(),
+            nft_contract_id: offer.nft_contract_id.clone(),
+            price: U128(offer.price),
+            quantity: offer.quantity,
+            token_id,
+            ft_token_id: offer.ft_token_id.clone(),
+        }
+    }
+}
+
+//a sealed bid when it's committed, and when an unrevealed one's deposit is released after the reveal phase
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SealedBidLog {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub bidder_id: AccountId,
+    pub deposit: U128,
+    //who the deposit went to instead of the bidder. Left out when it was refunded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forfeited_to: Option<AccountId>,
+}
+
+//a listing that was removed because the marketplace lost its approval, and what the caller was paid for it
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StaleListingLog {
+    pub seller: AccountId,
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub caller_id: AccountId,
+    pub reward: U128,
+}
+
+//a proposed or completed handover of the marketplace. new_owner_id is left out when a proposal is withdrawn
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OwnerLog {
+    pub owner_id: AccountId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_owner_id: Option<AccountId>,
+}
+
+//an admin role that was granted to or revoked from an account
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoleLog {
+    pub account_id: AccountId,
+    pub role: AdminRole,
+}
+
+//a new marketplace fee (in basis points) and when it applies
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OwnerCutLog {
+    pub owner_cut: u16,
+    pub effective_at: U64,
+}
+
+//a change of the account marketplace fees are paid to
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TreasuryLog {
+    pub old_treasury_id: AccountId,
+    pub treasury_id: AccountId,
+}
+
+//the terms set for an nft contract. config is left out when they're removed, unless a fee is kept until the
+//move to owner_cut has waited out the timelock
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionConfigLog {
+    pub nft_contract_id: AccountId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config: Option<CollectionConfig>,
+}
+
+//a part of the marketplace that was paused or unpaused
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PauseLog {
+    pub area: PauseArea,
+    pub paused: bool,
+}
+
+//which nft contracts can list on the marketplace from now on
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionModeLog {
+    pub mode: CollectionMode,
+}
+
+//nft contracts that were added to (listed is true) or removed from the allowlist or blocklist
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionListLog {
+    pub nft_contract_ids: Vec<AccountId>,
+    pub listed: bool,
+}
+
+//fungible tokens that couldn't be sent to an account and are kept for it to claim, or that it claimed
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtClaimLog {
+    pub account_id: AccountId,
+    pub ft_token_id: AccountId,
+    pub amount: U128,
+}
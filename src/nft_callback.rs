@@ -1,34 +1,82 @@
 use crate::{*, internal::hash_account_id};
+use near_contract_standards::non_fungible_token::approval::NonFungibleTokenApprovalReceiver;
 
-/// approval callbacks from NFT Contracts
+// approval callbacks from NFT Contracts
+
+//the sale terms a seller passes as the `msg` of nft_approve. The listing is created from these in one step
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde", tag = "sale_type", rename_all = "snake_case")]
+pub enum SaleArgs {
+    //sell the token for a fixed price in yoctoNEAR (or ft_token_id). It can't be bought from expires_at on, if set
+    FixedPrice {
+        price: U128,
+        ft_token_id: Option<AccountId>,
+        expires_at: Option<U64>,
+    },
+    //english auction between started_at (defaults to now) and end_at. The first bid must be at least starting_price.
+    //A bid within extension_window of end_at pushes it back by extension_duration (both default to the market's).
+    //The token only sells if the winning bid reaches reserve_price, and each bid must raise by min_increment.
+    //Anyone can end the auction early by paying buy_now_price
+    Auction {
+        starting_price: U128,
+        started_at: Option<U64>,
+        end_at: U64,
+        ft_token_id: Option<AccountId>,
+        extension_window: Option<U64>,
+        extension_duration: Option<U64>,
+        reserve_price: Option<U128>,
+        min_increment: Option<MinIncrement>,
+        buy_now_price: Option<U128>,
+    },
+    //dutch auction whose price falls from starting_price to floor_price between started_at (defaults to now) and
+    //end_at, following a linear or stepped curve. The first buyer pays the price at the time
+    DutchAuction {
+        starting_price: U128,
+        floor_price: U128,
+        started_at: Option<U64>,
+        end_at: U64,
+        curve: PriceCurve,
+        ft_token_id: Option<AccountId>,
+    },
+    //sealed auction: bids are committed as hashes between started_at (defaults to now) and end_at, then revealed
+    //until reveal_end_at. The highest revealed bid of at least starting_price wins. With vickrey it pays the
+    //second highest price instead. Unrevealed bids are refunded unless unrevealed_bids is "forfeit"
+    SealedAuction {
+        starting_price: U128,
+        started_at: Option<U64>,
+        end_at: U64,
+        reveal_end_at: U64,
+        reserve_price: Option<U128>,
+        unrevealed_bids: Option<UnrevealedBidPolicy>,
+        vickrey: Option<bool>,
+    },
+    //sell the token straight away to buyer_id for the amount they offered
+    AcceptOffer {
+        buyer_id: AccountId,
+    },
+    //sell the token to buyer_id's offer on any token of this nft contract
+    FillCollectionOffer {
+        buyer_id: AccountId,
+    },
+}
 
 /*
     trait that will be used as the callback from the NFT contract. When nft_approve is
     called, it will fire a cross contract call to this marketplace and this is the function
-    that is invoked. 
+    that is invoked. We implement the standard NEP-178 receiver trait.
 */
-trait NonFungibleTokenApprovalsReceiver {
-    fn nft_on_approve(
-        &mut self,
-        token_id: TokenId,
-        owner_id: AccountId,
-        approval_id: u64,
-        msg: String,
-    );
-}
 
 //implementation of the trait
 #[near_bindgen]
-impl NonFungibleTokenApprovalsReceiver for Marketplace {
-    /// where we add the sale because we know nft owner can only call nft_approve
-
+impl NonFungibleTokenApprovalReceiver for Marketplace {
+    // where we add the sale because we know nft owner can only call nft_approve
     fn nft_on_approve(
         &mut self,
         token_id: TokenId,
         owner_id: AccountId,
         approval_id: u64,
         msg: String,
-    ) {
+    ) -> PromiseOrValue<String> {
         // get the contract ID which is the predecessor
         let nft_contract_id = env::predecessor_account_id();
         //get the signer which is the person who initiated the transaction
@@ -48,43 +96,195 @@ impl NonFungibleTokenApprovalsReceiver for Marketplace {
             "owner_id should be signer_id"
         );
 
-        //we need to enforce that the user has enough storage for 1 EXTRA sale.  
+        //parse the sale terms so the listing is complete as soon as it's created
+        let sale_args: SaleArgs = near_sdk::serde_json::from_str(&msg)
+            .unwrap_or_else(|err| panic!("Invalid sale terms in msg: {}", err));
+        //the listing starts out with no terms, the sale type fills them in
+        let mut listing = Listing {
+            seller: owner_id.clone(), //owner of the sale / token
+            approval_id, //approval ID for that token that was given to the market
+            nft_contract_id: nft_contract_id.to_string(), //NFT contract the token was minted on
+            token_id: token_id.clone(), //the actual token ID
 
-        //get the storage for a sale. dot 0 converts from U128 to u128
-        // let storage_amount = self.storage_minimum_balance();
-        let storage_amount = 0;
-        //get the total storage paid by the owner
-        let owner_paid_storage = self.storage_deposits.get(&signer_id).unwrap_or(0);
-        //get the storage required which is simply the storage for the number of sales they have + 1 
-        let signer_storage_required = (self.get_supply_by_owner_id(signer_id).0 + 1) as u128 * storage_amount;
-        
-        //make sure that the total paid is >= the required storage
-        assert!(
-            owner_paid_storage >= signer_storage_required,
-            "Insufficient storage paid: {}, for {} sales at {} rate of per sale",
-            owner_paid_storage, signer_storage_required / STORAGE_PER_SALE, STORAGE_PER_SALE
-        );
+            starting_price: 0,
+            ft_token_id: None,
+            end_at: 0,
+            started_at: 0,
+            highest_bidder: None,
+            highest_price: 0,
+            is_auction: false,
+            extension_window: 0,
+            extension_duration: 0,
+            reserve_price: 0,
+            min_increment: MinIncrement::default(),
+            buy_now_price: None,
+            dutch_auction: None,
+            sealed_auction: None,
+            is_pending: false,
+        };
+        match sale_args {
+            SaleArgs::FixedPrice {
+                price,
+                ft_token_id,
+                expires_at,
+            } => {
+                assert!(price.0 > 0, "Price must be greater than 0");
+                let expires_at = expires_at.map_or(0, |expires_at| expires_at.0);
+                internal::assert_valid_expiry(expires_at);
+                listing.starting_price = price.0;
+                listing.ft_token_id = ft_token_id;
+                listing.end_at = expires_at;
+            }
+            SaleArgs::Auction {
+                starting_price,
+                started_at,
+                end_at,
+                ft_token_id,
+                extension_window,
+                extension_duration,
+                reserve_price,
+                min_increment,
+                buy_now_price,
+            } => {
+                let started_at = started_at
+                    .map(|started_at| started_at.0)
+                    .unwrap_or_else(env::block_timestamp);
+                internal::assert_valid_auction_window(started_at, end_at.0);
+                listing.is_auction = true;
+                listing.starting_price = starting_price.0;
+                listing.ft_token_id = ft_token_id;
+                listing.started_at = started_at;
+                listing.end_at = end_at.0;
+                listing.extension_window =
+                    extension_window.map_or(self.auction_extension_window, |window| window.0);
+                listing.extension_duration =
+                    extension_duration.map_or(self.auction_extension_duration, |duration| duration.0);
+                listing.reserve_price = reserve_price.map_or(0, |reserve_price| reserve_price.0);
+                listing.min_increment = min_increment.unwrap_or_default();
+                if let Some(buy_now_price) = buy_now_price {
+                    assert!(
+                        buy_now_price.0 > 0
+                            && buy_now_price.0 >= listing.starting_price
+                            && buy_now_price.0 >= listing.reserve_price,
+                        "buy_now_price must be at least the starting and reserve price"
+                    );
+                    listing.buy_now_price = Some(buy_now_price.0);
+                }
+            }
+            SaleArgs::DutchAuction {
+                starting_price,
+                floor_price,
+                started_at,
+                end_at,
+                curve,
+                ft_token_id,
+            } => {
+                let started_at = started_at
+                    .map(|started_at| started_at.0)
+                    .unwrap_or_else(env::block_timestamp);
+                internal::assert_valid_auction_window(started_at, end_at.0);
+                assert!(floor_price.0 > 0, "floor_price must be greater than 0");
+                assert!(
+                    starting_price.0 > floor_price.0,
+                    "starting_price must be above floor_price"
+                );
+                if let PriceCurve::Stepped { steps } = curve {
+                    assert!(steps > 0, "A stepped curve needs at least one step");
+                }
+                listing.starting_price = starting_price.0;
+                listing.ft_token_id = ft_token_id;
+                listing.started_at = started_at;
+                listing.end_at = end_at.0;
+                listing.dutch_auction = Some(DutchAuction { floor_price, curve });
+            }
+            SaleArgs::SealedAuction {
+                starting_price,
+                started_at,
+                end_at,
+                reveal_end_at,
+                reserve_price,
+                unrevealed_bids,
+                vickrey,
+            } => {
+                let started_at = started_at
+                    .map(|started_at| started_at.0)
+                    .unwrap_or_else(env::block_timestamp);
+                internal::assert_valid_auction_window(started_at, end_at.0);
+                assert!(reveal_end_at.0 > end_at.0, "reveal_end_at must be after end_at");
+                listing.is_auction = true;
+                listing.starting_price = starting_price.0;
+                listing.started_at = started_at;
+                listing.end_at = end_at.0;
+                listing.reserve_price = reserve_price.map_or(0, |reserve_price| reserve_price.0);
+                listing.sealed_auction = Some(SealedAuction {
+                    reveal_end_at: reveal_end_at.0,
+                    unrevealed_bids: unrevealed_bids.unwrap_or(UnrevealedBidPolicy::Refund),
+                    vickrey: vickrey.unwrap_or(false),
+                    second_price: 0,
+                });
+            }
+            SaleArgs::AcceptOffer { buyer_id } => {
+                //no listing is created, the offer pays for the token using the approval we were just given
+                return PromiseOrValue::Promise(self.internal_accept_offer(
+                    nft_contract_id,
+                    token_id,
+                    owner_id,
+                    approval_id,
+                    buyer_id,
+                ));
+            }
+            SaleArgs::FillCollectionOffer { buyer_id } => {
+                return PromiseOrValue::Promise(self.internal_fill_collection_offer(
+                    nft_contract_id,
+                    token_id,
+                    owner_id,
+                    approval_id,
+                    buyer_id,
+                ));
+            }
+        };
+        self.assert_not_paused(PauseArea::Listing);
+        self.assert_can_list(&nft_contract_id);
+        //only fungible tokens the owner has allowed can be used to price a listing
+        if let Some(ft_token_id) = listing.ft_token_id.as_ref() {
+            assert!(
+                self.ft_token_ids.contains(ft_token_id),
+                "{} is not an accepted fungible token",
+                ft_token_id
+            );
+        }
 
         //create the unique sale ID which is the contract + DELIMITER + token ID
         let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+
+        //re-approving can't wipe out a sale that's in the middle of a purchase or holding someone's bid
+        let existing_listing = self.listings.get(&contract_and_token_id);
+        if let Some(listing) = existing_listing.as_ref() {
+            assert!(!listing.is_pending, "Purchase in progress");
+            assert!(listing.highest_bidder.is_none(), "Listing has an active bid");
+        }
+        //if the token changed hands, the old seller's listing is dead. Take it down so it's no longer theirs and
+        //they get back the storage it was using
+        let existing_listing = match existing_listing {
+            Some(listing) if listing.seller != owner_id => {
+                self.internal_remove_listing(nft_contract_id.clone(), token_id.clone());
+                None
+            }
+            existing_listing => existing_listing,
+        };
+        //sealed bids left over from the last auction could otherwise be revealed in this one
+        assert!(
+            self.sealed_bids.get(&contract_and_token_id).is_none(),
+            "Sealed bids from the last auction haven't been released yet"
+        );
+        //the same goes for the bid history, which was kept around in case the last sale was disputed
+        self.internal_clear_bid_history(&contract_and_token_id);
+
+        //measure the storage the listing takes up so we can charge it to the seller's storage balance
+        let initial_storage_usage = env::storage_usage();
         
         //insert the key value pair into the sales map. Key is the unique ID. value is the sale object
-        self.listings.insert(
-            &contract_and_token_id,
-            &Listing {
-                seller: owner_id.clone(), //owner of the sale / token
-                approval_id, //approval ID for that token that was given to the market
-                nft_contract_id: nft_contract_id.to_string(), //NFT contract the token was minted on
-                token_id: token_id.clone(), //the actual token ID
-
-                starting_price: 0,
-                end_at: 0,
-                started_at: 0,
-                highest_bidder: None,
-                highest_price: 0,
-                is_auction: false
-           },
-        );
+        self.listings.insert(&contract_and_token_id, &listing);
 
         //Extra functionality that populates collections necessary for the view calls 
 
@@ -125,5 +325,32 @@ impl NonFungibleTokenApprovalsReceiver for Marketplace {
         //insert the set back into the collection for the given nft contract ID
         self.by_nft_contract_id
             .insert(&nft_contract_id, &by_nft_contract_id);
+
+        //make sure the seller has paid for the storage the new listing is using
+        self.internal_charge_storage(&owner_id, initial_storage_usage);
+
+        if existing_listing.is_some() {
+            EventLogVariant::ListingUpdated(ListingLog::new(&listing)).emit();
+        } else {
+            EventLogVariant::ListingCreated(ListingLog::new(&listing)).emit();
+        }
+
+        PromiseOrValue::Value("true".to_string())
     }
-}
\ No newline at end of file
+}
+
+#[near_bindgen]
+impl Marketplace {
+    //Called by nft contracts that let the marketplace know when its approval for a token is revoked. The listing
+    //can't sell anymore, so it's cancelled and any bid refunded. A listing that's in the middle of being bought is
+    //left alone, its transfer will fail and refund the buyer
+    pub fn nft_on_revoke(&mut self, token_id: TokenId) {
+        let nft_contract_id = env::predecessor_account_id();
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        if let Some(listing) = self.listings.get(&contract_and_token_id) {
+            if !listing.is_pending {
+                self.internal_cancel_listing(nft_contract_id, token_id);
+            }
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{*, internal::hash_contract_and_token_id};
+
+// offers on individual tokens
+
+//an offer to buy a token for `amount` yoctoNEAR, held in escrow until it's accepted, withdrawn or expires
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Offer {
+    //account that made the offer and gets the token if it's accepted
+    pub buyer_id: AccountId,
+    //nft contract where the token was minted
+    pub nft_contract_id: AccountId,
+    //token the offer is for
+    pub token_id: TokenId,
+    //escrowed amount in yoctoNEAR
+    pub amount: u128,
+    //the offer can't be accepted from this point on, and anyone can refund it
+    pub expires_at: u64,
+}
+
+//an offer to buy up to `quantity` tokens of any kind from an nft contract for `price` each. The price of every
+//token left to buy is held in escrow, in NEAR or ft_token_id
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionOffer {
+    //account that made the offer and gets the tokens it buys
+    pub buyer_id: AccountId,
+    //nft contract any token can be sold from
+    pub nft_contract_id: AccountId,
+    //what's paid for each token, in yoctoNEAR or the smallest unit of ft_token_id
+    pub price: u128,
+    //how many more tokens the offer will buy
+    pub quantity: u32,
+    //fungible token the offer is paid in. None means it's paid in NEAR
+    pub ft_token_id: Option<AccountId>,
+}
+
+impl CollectionOffer {
+    //the amount held in escrow for the tokens left to buy
+    pub(crate) fn total(&self) -> u128 {
+        self.price * u128::from(self.quantity)
+    }
+}
+
+#[near_bindgen]
+impl Marketplace {
+    //Offers the attached deposit for a token, whether or not it's listed. The deposit is held in escrow until the
+    //owner accepts it (by calling nft_approve with an `accept_offer` msg), the buyer withdraws it, or it expires.
+    //Making another offer on the same token replaces the previous one and refunds it. The offer's storage is
+    //charged to the buyer's storage balance.
+    #[payable]
+    pub fn make_offer(&mut self, nft_contract_id: AccountId, token_id: TokenId, expires_at: U64) {
+        self.assert_not_paused(PauseArea::Bidding);
+        let buyer_id = env::predecessor_account_id();
+        let amount = env::attached_deposit();
+        assert!(amount > 0, "Offer amount must be greater than 0");
+        assert!(expires_at.0 > env::block_timestamp(), "expires_at must be in the future");
+
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        if let Some(listing) = self.listings.get(&contract_and_token_id) {
+            assert!(listing.seller != buyer_id, "Can't make an offer on your own listing");
+        }
+
+        //measure the storage the offer takes up so we can charge it to the buyer's storage balance
+        let initial_storage_usage = env::storage_usage();
+
+        let offer = Offer {
+            buyer_id: buyer_id.clone(),
+            nft_contract_id,
+            token_id,
+            amount,
+            expires_at: expires_at.0,
+        };
+        let mut offers = self.offers.get(&contract_and_token_id).unwrap_or_else(|| {
+            UnorderedMap::new(
+                StorageKey::OffersInner {
+                    //we get a new unique prefix for the collection by hashing the unique sale ID
+                    contract_and_token_id_hash: hash_contract_and_token_id(&contract_and_token_id),
+                }
+                .try_to_vec()
+                .unwrap(),
+            )
+        });
+        let previous_offer = offers.insert(&buyer_id, &offer);
+        self.offers.insert(&contract_and_token_id, &offers);
+
+        self.internal_charge_storage(&buyer_id, initial_storage_usage);
+
+        //the replaced offer's amount is still in escrow, so give it back
+        if let Some(previous_offer) = previous_offer {
+            self.internal_transfer(&None, buyer_id, previous_offer.amount);
+            EventLogVariant::OfferWithdrawn(OfferLog::new(&previous_offer)).emit();
+        }
+        EventLogVariant::OfferMade(OfferLog::new(&offer)).emit();
+    }
+
+    //Withdraws the caller's offer on a token and refunds the escrowed amount
+    #[payable]
+    pub fn withdraw_offer(&mut self, nft_contract_id: AccountId, token_id: TokenId) {
+        //make sure the user attaches exactly 1 yoctoNEAR for security purposes.
+        assert_one_yocto();
+
+        let buyer_id = env::predecessor_account_id();
+        let offer = self
+            .internal_remove_offer(&nft_contract_id, &token_id, &buyer_id)
+            .expect("No offer from this account");
+
+        self.internal_transfer(&None, buyer_id, offer.amount);
+        EventLogVariant::OfferWithdrawn(OfferLog::new(&offer)).emit();
+    }
+
+    //Refunds an offer that has expired. Anyone can call this so escrowed funds never get stuck
+    pub fn refund_expired_offer(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        buyer_id: AccountId,
+    ) {
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        let expires_at = self
+            .offers
+            .get(&contract_and_token_id)
+            .and_then(|offers| offers.get(&buyer_id))
+            .expect("No offer from this account")
+            .expires_at;
+        assert!(env::block_timestamp() >= expires_at, "Offer has not expired");
+
+        let offer = self
+            .internal_remove_offer(&nft_contract_id, &token_id, &buyer_id)
+            .unwrap();
+
+        self.internal_transfer(&None, buyer_id, offer.amount);
+        EventLogVariant::OfferExpired(OfferLog::new(&offer)).emit();
+    }
+
+    //Offers to buy up to `quantity` tokens from an nft contract for `price` yoctoNEAR each. The attached deposit
+    //must cover all of them and is held in escrow. Any holder can fill the offer by calling nft_approve with a
+    //`fill_collection_offer` msg. Offers paid in a fungible token are made through ft_transfer_call instead
+    #[payable]
+    pub fn make_collection_offer(&mut self, nft_contract_id: AccountId, price: U128, quantity: u32) {
+        let buyer_id = env::predecessor_account_id();
+        let total = price
+            .0
+            .checked_mul(u128::from(quantity))
+            .expect("Offer total overflows");
+        assert_eq!(
+            env::attached_deposit(),
+            total,
+            "Attached deposit must be exactly {}",
+            total
+        );
+
+        self.internal_add_collection_offer(nft_contract_id, buyer_id, price.0, quantity, None);
+    }
+
+    //Withdraws the caller's collection offer and refunds what's still in escrow
+    #[payable]
+    pub fn withdraw_collection_offer(&mut self, nft_contract_id: AccountId) {
+        //make sure the user attaches exactly 1 yoctoNEAR for security purposes.
+        assert_one_yocto();
+
+        let buyer_id = env::predecessor_account_id();
+        let offer = self
+            .internal_remove_collection_offer(&nft_contract_id, &buyer_id)
+            .expect("No collection offer from this account");
+
+        self.internal_transfer(&offer.ft_token_id, buyer_id, offer.total());
+        EventLogVariant::CollectionOfferWithdrawn(CollectionOfferLog::new(&offer, None)).emit();
+    }
+}
@@ -0,0 +1,125 @@
+use crate::*;
+
+// contract ownership and admin roles
+
+//what an admin is allowed to do on the owner's behalf. The owner can always do all of it
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum AdminRole {
+    //change the marketplace fee
+    Fees,
+    //pause and unpause the marketplace
+    Pause,
+    //manage which accounts (fungible tokens, nft contracts) the marketplace accepts
+    Allowlist,
+}
+
+#[near_bindgen]
+impl Marketplace {
+    //Proposes `new_owner` as the next owner of the marketplace, who takes over once they call accept_owner. Only the
+    //owner can do this, and proposing None withdraws the proposal
+    #[payable]
+    pub fn propose_owner(&mut self, new_owner: Option<AccountId>) {
+        //make sure the owner attaches exactly 1 yoctoNEAR for security purposes.
+        assert_one_yocto();
+        self.assert_owner();
+
+        self.proposed_owner = new_owner.clone();
+        EventLogVariant::OwnerProposed(OwnerLog {
+            owner_id: self.owner.clone(),
+            new_owner_id: new_owner,
+        })
+        .emit();
+    }
+
+    //Makes the caller the owner of the marketplace, if the owner proposed them. Fees keep going to the treasury
+    //until the new owner changes it
+    #[payable]
+    pub fn accept_owner(&mut self) {
+        //make sure the new owner attaches exactly 1 yoctoNEAR for security purposes.
+        assert_one_yocto();
+        let new_owner = env::predecessor_account_id();
+        assert!(
+            self.proposed_owner.as_ref() == Some(&new_owner),
+            "Only the proposed owner can accept ownership"
+        );
+
+        self.proposed_owner = None;
+        let owner = std::mem::replace(&mut self.owner, new_owner.clone());
+        EventLogVariant::OwnerTransferred(OwnerLog {
+            owner_id: owner,
+            new_owner_id: Some(new_owner),
+        })
+        .emit();
+    }
+
+    //Lets `account_id` act as an admin with `role`. Only the owner can do this
+    pub fn grant_role(&mut self, account_id: AccountId, role: AdminRole) {
+        self.assert_owner();
+        let mut roles = self.admins.get(&account_id).unwrap_or_default();
+        if roles.contains(&role) {
+            return;
+        }
+        roles.push(role);
+        self.admins.insert(&account_id, &roles);
+        EventLogVariant::RoleGranted(RoleLog { account_id, role }).emit();
+    }
+
+    //Takes `role` away from `account_id`. Only the owner can do this
+    pub fn revoke_role(&mut self, account_id: AccountId, role: AdminRole) {
+        self.assert_owner();
+        let mut roles = self.admins.get(&account_id).unwrap_or_default();
+        if !roles.contains(&role) {
+            return;
+        }
+        roles.retain(|granted| granted != &role);
+        if roles.is_empty() {
+            self.admins.remove(&account_id);
+        } else {
+            self.admins.insert(&account_id, &roles);
+        }
+        EventLogVariant::RoleRevoked(RoleLog { account_id, role }).emit();
+    }
+
+    //returns the owner of the marketplace, who also receives its fees
+    pub fn get_owner(&self) -> AccountId {
+        self.owner.clone()
+    }
+
+    //returns the account the owner has proposed as the next owner, if any
+    pub fn get_proposed_owner(&self) -> Option<AccountId> {
+        self.proposed_owner.clone()
+    }
+
+    //returns the admin roles `account_id` has been granted
+    pub fn get_roles(&self, account_id: AccountId) -> Vec<AdminRole> {
+        self.admins.get(&account_id).unwrap_or_default()
+    }
+
+    //returns paginated admins and the roles they've been granted
+    pub fn get_admins(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<(AccountId, Vec<AdminRole>)> {
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+        self.admins
+            .iter()
+            .skip(start as usize)
+            .take(limit.unwrap_or(0) as usize)
+            .collect()
+    }
+}
+
+impl Marketplace {
+    //used to make sure only the owner or an admin with `role` can call a method
+    pub(crate) fn assert_role(&self, role: AdminRole) {
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == self.owner
+                || self.admins.get(&caller).is_some_and(|roles| roles.contains(&role)),
+            "Only the owner or an admin with the {:?} role can call this method",
+            role
+        );
+    }
+}
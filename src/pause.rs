@@ -0,0 +1,74 @@
+use crate::*;
+
+// emergency circuit breakers
+
+//a part of the marketplace that can be paused on its own
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum PauseArea {
+    //creating listings and changing their terms (nft_on_approve, create_listing, set_price)
+    Listing,
+    //buying listings and selling to offers (purchase_nft, accepting offers)
+    Buying,
+    //placing bids and offers (bid, commit_bid, make_offer, make_collection_offer)
+    Bidding,
+    //settling auctions that have ended (settle_auction)
+    Settlement,
+}
+
+//which parts of the marketplace are paused. Withdrawing escrowed funds and storage is never paused
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Paused {
+    pub listing: bool,
+    pub buying: bool,
+    pub bidding: bool,
+    pub settlement: bool,
+}
+
+impl Paused {
+    fn flag(&mut self, area: PauseArea) -> &mut bool {
+        match area {
+            PauseArea::Listing => &mut self.listing,
+            PauseArea::Buying => &mut self.buying,
+            PauseArea::Bidding => &mut self.bidding,
+            PauseArea::Settlement => &mut self.settlement,
+        }
+    }
+
+    //whether `area` is paused
+    pub(crate) fn is_paused(&self, area: PauseArea) -> bool {
+        match area {
+            PauseArea::Listing => self.listing,
+            PauseArea::Buying => self.buying,
+            PauseArea::Bidding => self.bidding,
+            PauseArea::Settlement => self.settlement,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Marketplace {
+    //Pauses or unpauses a part of the marketplace. Only the owner or a pause admin can do this
+    pub fn set_paused(&mut self, area: PauseArea, paused: bool) {
+        self.assert_role(AdminRole::Pause);
+        let flag = self.paused.flag(area);
+        if *flag == paused {
+            return;
+        }
+        *flag = paused;
+        EventLogVariant::PauseToggled(PauseLog { area, paused }).emit();
+    }
+
+    //returns which parts of the marketplace are paused
+    pub fn get_paused(&self) -> Paused {
+        self.paused.clone()
+    }
+}
+
+impl Marketplace {
+    //used to make sure a method's part of the marketplace isn't paused
+    pub(crate) fn assert_not_paused(&self, area: PauseArea) {
+        assert!(!self.paused.is_paused(area), "{:?} is paused", area);
+    }
+}
@@ -1,10 +1,19 @@
 use crate::*;
 
+//the anti-sniping extension auctions get by default, in nanoseconds
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AuctionExtension {
+    pub window: U64,
+    pub duration: U64,
+}
+
 #[near_bindgen]
 impl Marketplace {
-    /// views
+    // views
     
-    //returns the number of sales the marketplace has up (as a string)
+    //returns the number of sales the marketplace has up (as a string). Unlike the per owner and per contract
+    //counts, this includes expired listings that haven't been pruned yet
     pub fn get_supply_sales(
         &self,
     ) -> U64 {
@@ -20,9 +29,12 @@ impl Marketplace {
         //get the set of sales for the given owner Id
         let by_owner_id = self.by_owner_id.get(&account_id);
         
-        //if there as some set, we return the length but if there wasn't a set, we return 0
+        //if there as some set, we count the listings that haven't expired but if there wasn't a set, we return 0
         if let Some(by_owner_id) = by_owner_id {
-            U64(by_owner_id.len())
+            U64(by_owner_id
+                .iter()
+                .filter(|token_id| !self.listings.get(token_id).unwrap().is_expired())
+                .count() as u64)
         } else {
             U64(0)
         }
@@ -52,12 +64,14 @@ impl Marketplace {
         
         //iterate through the keys vector
         keys.iter()
-            //skip to the index we specified in the start variable
-            .skip(start as usize) 
-            //take the first "limit" elements in the vector. If we didn't specify a limit, use 0
-            .take(limit.unwrap_or(0) as usize) 
             //we'll map the token IDs which are strings into Sale objects
             .map(|token_id| self.listings.get(&token_id).unwrap())
+            //expired listings can't be bought, so they're left out until they're pruned
+            .filter(|listing| !listing.is_expired())
+            //skip to the index among the unexpired listings we specified in the start variable
+            .skip(start as usize)
+            //take the first "limit" elements in the vector. If we didn't specify a limit, use 0
+            .take(limit.unwrap_or(0) as usize)
             //since we turned the keys into an iterator, we need to turn it back into a vector to return
             .collect()
     }
@@ -70,9 +84,18 @@ impl Marketplace {
         //get the set of tokens for associated with the given nft contract
         let by_nft_contract_id = self.by_nft_contract_id.get(&nft_contract_id);
         
-        //if there was some set, return it's length. Otherwise return 0
+        //if there was some set, return how many of its listings haven't expired. Otherwise return 0
         if let Some(by_nft_contract_id) = by_nft_contract_id {
-            U64(by_nft_contract_id.len())
+            U64(by_nft_contract_id
+                .iter()
+                .filter(|token_id| {
+                    !self
+                        .listings
+                        .get(&format!("{}{}{}", nft_contract_id, DELIMETER, token_id))
+                        .unwrap()
+                        .is_expired()
+                })
+                .count() as u64)
         } else {
             U64(0)
         }
@@ -103,12 +126,14 @@ impl Marketplace {
         
         //iterate through the keys vector
         keys.iter()
-            //skip to the index we specified in the start variable
-            .skip(start as usize) 
-            //take the first "limit" elements in the vector. If we didn't specify a limit, use 0
-            .take(limit.unwrap_or(0) as usize) 
             //we'll map the token IDs which are strings into Sale objects by passing in the unique sale ID (contract + DELIMITER + token ID)
             .map(|token_id| self.listings.get(&format!("{}{}{}", nft_contract_id, DELIMETER, token_id)).unwrap())
+            //expired listings can't be bought, so they're left out until they're pruned
+            .filter(|listing| !listing.is_expired())
+            //skip to the index among the unexpired listings we specified in the start variable
+            .skip(start as usize)
+            //take the first "limit" elements in the vector. If we didn't specify a limit, use 0
+            .take(limit.unwrap_or(0) as usize)
             //since we turned the keys into an iterator, we need to turn it back into a vector to return
             .collect()
     }
@@ -119,4 +144,68 @@ impl Marketplace {
         //we're not guaranteed that the unique sale ID passed in will be valid.
         self.listings.get(&nft_contract_token)
     }
-}
\ No newline at end of file
+
+    //returns what a listing can be bought for right now, which for a dutch auction depends on the block timestamp.
+    //None if it isn't listed or it's an auction that can only be won by bidding
+    pub fn get_current_price(&self, listing_id: ContractAndTokenId) -> Option<U128> {
+        self.listings
+            .get(&listing_id)
+            .and_then(|listing| listing.current_price())
+            .map(U128)
+    }
+
+    //returns paginated bids from a token's bid history, oldest first. Only the last MAX_BID_HISTORY bids are kept,
+    //and they stay after the auction is settled or cancelled until the token is listed again
+    pub fn get_bids(
+        &self,
+        listing_id: ContractAndTokenId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Bid> {
+        let bid_history = if let Some(bid_history) = self.bid_history.get(&listing_id) {
+            bid_history
+        } else {
+            return vec![];
+        };
+
+        //where to start pagination - if we have a from_index, we'll use that - otherwise start from the oldest bid
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+
+        (bid_history.first..bid_history.first + bid_history.len)
+            .skip(start as usize)
+            //take the first "limit" bids. If we didn't specify a limit, return all of them
+            .take(limit.unwrap_or(MAX_BID_HISTORY) as usize)
+            .map(|index| bid_history.bids.get(&index).unwrap())
+            .collect()
+    }
+
+    //returns the fungible tokens (besides NEAR) that listings can be priced in
+    pub fn get_supported_ft_token_ids(&self) -> Vec<AccountId> {
+        self.ft_token_ids.to_vec()
+    }
+
+    //returns the open offers on a token, including expired ones that haven't been refunded yet
+    pub fn get_offers(&self, nft_contract_id: AccountId, token_id: TokenId) -> Vec<Offer> {
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        self.offers
+            .get(&contract_and_token_id)
+            .map(|offers| offers.values().collect())
+            .unwrap_or_default()
+    }
+
+    //returns the open offers on any token of an nft contract
+    pub fn get_collection_offers(&self, nft_contract_id: AccountId) -> Vec<CollectionOffer> {
+        self.collection_offers
+            .get(&nft_contract_id)
+            .map(|offers| offers.values().collect())
+            .unwrap_or_default()
+    }
+
+    //returns the anti-sniping extension auctions get when the seller doesn't choose their own
+    pub fn get_auction_extension(&self) -> AuctionExtension {
+        AuctionExtension {
+            window: U64(self.auction_extension_window),
+            duration: U64(self.auction_extension_duration),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{*, internal::hash_contract_and_token_id};
+use near_sdk::json_types::Base64VecU8;
+
+// sealed bid (commit-reveal) auctions
+
+//what happens to bids that are never revealed
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum UnrevealedBidPolicy {
+    //the escrowed deposit goes back to the bidder
+    Refund,
+    //the escrowed deposit goes to the seller
+    Forfeit,
+}
+
+//the terms of a sealed auction. Bids are committed between started_at and end_at, then revealed until reveal_end_at
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SealedAuction {
+    pub reveal_end_at: u64,
+    pub unrevealed_bids: UnrevealedBidPolicy,
+    //the winner pays the second highest revealed bid (or the reserve/starting price if that's higher)
+    pub vickrey: bool,
+    //the second highest revealed bid so far
+    pub second_price: u128,
+}
+
+//a committed bid that hasn't been revealed yet
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct SealedBid {
+    //sha256 of `"<amount>:<salt>"`, with the amount in yoctoNEAR
+    pub bid_hash: Vec<u8>,
+    //escrowed yoctoNEAR. It has to cover the bid once it's revealed
+    pub deposit: u128,
+    //when the bid can no longer be revealed and its deposit is released
+    pub reveal_end_at: u64,
+    //who gets the deposit if the bid is never revealed. None means it's refunded to the bidder
+    pub forfeit_to: Option<AccountId>,
+}
+
+#[near_bindgen]
+impl Marketplace {
+    //Commits a sealed bid on an auction during its bidding window. `bid_hash` is the sha256 of `"<amount>:<salt>"`
+    //and the attached deposit is held in escrow, so it has to be at least the amount. Committing again replaces the
+    //previous bid and refunds it. The commitment's storage is charged to the bidder's storage balance
+    #[payable]
+    pub fn commit_bid(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        bid_hash: Base64VecU8,
+    ) {
+        self.assert_not_paused(PauseArea::Bidding);
+        let bidder_id = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+        assert!(deposit > 0, "Requires a deposit to escrow the bid");
+        assert_eq!(bid_hash.0.len(), 32, "bid_hash must be a sha256 hash");
+
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        let listing = self
+            .listings
+            .get(&contract_and_token_id)
+            .expect("NFT not listed yet");
+        let sealed_auction = listing.sealed_auction.clone().expect("Not a sealed auction");
+        assert!(Self::is_on_auction(listing.clone()), "Auction not on");
+        assert!(listing.seller != bidder_id, "Invalid bid");
+
+        //measure the storage the bid takes up so we can charge it to the bidder's storage balance
+        let initial_storage_usage = env::storage_usage();
+
+        let sealed_bid = SealedBid {
+            bid_hash: bid_hash.0,
+            deposit,
+            reveal_end_at: sealed_auction.reveal_end_at,
+            forfeit_to: match sealed_auction.unrevealed_bids {
+                UnrevealedBidPolicy::Refund => None,
+                UnrevealedBidPolicy::Forfeit => Some(listing.seller),
+            },
+        };
+        let mut sealed_bids = self.sealed_bids.get(&contract_and_token_id).unwrap_or_else(|| {
+            UnorderedMap::new(
+                StorageKey::SealedBidsInner {
+                    //we get a new unique prefix for the collection by hashing the unique sale ID
+                    contract_and_token_id_hash: hash_contract_and_token_id(&contract_and_token_id),
+                }
+                .try_to_vec()
+                .unwrap(),
+            )
+        });
+        let previous_bid = sealed_bids.insert(&bidder_id, &sealed_bid);
+        self.sealed_bids.insert(&contract_and_token_id, &sealed_bids);
+
+        self.internal_charge_storage(&bidder_id, initial_storage_usage);
+
+        //the replaced bid's deposit is still in escrow, so give it back
+        if let Some(previous_bid) = previous_bid {
+            self.internal_transfer(&None, bidder_id.clone(), previous_bid.deposit);
+        }
+        EventLogVariant::BidCommitted(SealedBidLog {
+            nft_contract_id,
+            token_id,
+            bidder_id,
+            deposit: U128(deposit),
+            forfeited_to: None,
+        })
+        .emit();
+    }
+
+    //Reveals a committed bid after bidding closes. A bid that beats the highest revealed bid takes its place (the
+    //previous one is refunded) and the part of its deposit above the amount is refunded straight away. Any other
+    //bid is refunded in full
+    pub fn reveal_bid(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        amount: U128,
+        salt: String,
+    ) {
+        let bidder_id = env::predecessor_account_id();
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        let mut listing = self
+            .listings
+            .get(&contract_and_token_id)
+            .expect("NFT not listed yet");
+        let mut sealed_auction = listing.sealed_auction.clone().expect("Not a sealed auction");
+        assert!(
+            env::block_timestamp() >= listing.end_at
+                && env::block_timestamp() < sealed_auction.reveal_end_at,
+            "Not in the reveal phase"
+        );
+
+        //check the bid against its commitment before we let go of it
+        let sealed_bid = self
+            .sealed_bids
+            .get(&contract_and_token_id)
+            .and_then(|sealed_bids| sealed_bids.get(&bidder_id))
+            .expect("No sealed bid from this account");
+        let bid_hash = env::sha256(format!("{}:{}", amount.0, salt).as_bytes());
+        assert!(bid_hash == sealed_bid.bid_hash, "Revealed bid doesn't match the commitment");
+        assert!(amount.0 <= sealed_bid.deposit, "Deposit doesn't cover the bid");
+        self.internal_remove_sealed_bid(&contract_and_token_id, &bidder_id);
+        self.internal_record_bid(&contract_and_token_id, &bidder_id, amount.0);
+
+        //only a bid of at least the starting price can win
+        let mut refund = sealed_bid.deposit;
+        if amount.0 >= listing.starting_price.max(1) {
+            if amount.0 > listing.highest_price {
+                //the bid that was winning is now the runner up
+                if let Some(previous_bidder) = listing.highest_bidder.take() {
+                    self.internal_transfer(&None, previous_bidder.clone(), listing.highest_price);
+                    EventLogVariant::OutbidRefund(BidLog {
+                        nft_contract_id: nft_contract_id.clone(),
+                        token_id: token_id.clone(),
+                        bidder_id: previous_bidder,
+                        amount: U128(listing.highest_price),
+                        end_at: U64(sealed_auction.reveal_end_at),
+                        ft_token_id: None,
+                    })
+                    .emit();
+                    sealed_auction.second_price = listing.highest_price;
+                }
+                listing.highest_bidder = Some(bidder_id.clone());
+                listing.highest_price = amount.0;
+                refund -= amount.0;
+            } else {
+                sealed_auction.second_price = sealed_auction.second_price.max(amount.0);
+            }
+            listing.sealed_auction = Some(sealed_auction.clone());
+            self.listings.insert(&contract_and_token_id, &listing);
+        }
+
+        if refund > 0 {
+            self.internal_transfer(&None, bidder_id.clone(), refund);
+        }
+        EventLogVariant::BidRevealed(BidLog {
+            nft_contract_id,
+            token_id,
+            bidder_id,
+            amount,
+            end_at: U64(sealed_auction.reveal_end_at),
+            ft_token_id: None,
+        })
+        .emit();
+    }
+
+    //Releases the deposit of a bid that wasn't revealed in time, either back to the bidder or to the seller
+    //depending on the auction's policy. Anyone can call this once the reveal phase is over
+    pub fn release_unrevealed_bid(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        bidder_id: AccountId,
+    ) {
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        let reveal_end_at = self
+            .sealed_bids
+            .get(&contract_and_token_id)
+            .and_then(|sealed_bids| sealed_bids.get(&bidder_id))
+            .expect("No sealed bid from this account")
+            .reveal_end_at;
+        assert!(env::block_timestamp() >= reveal_end_at, "Bid can still be revealed");
+
+        let sealed_bid = self
+            .internal_remove_sealed_bid(&contract_and_token_id, &bidder_id)
+            .unwrap();
+        let receiver_id = sealed_bid.forfeit_to.clone().unwrap_or_else(|| bidder_id.clone());
+        self.internal_transfer(&None, receiver_id, sealed_bid.deposit);
+        EventLogVariant::UnrevealedBidReleased(SealedBidLog {
+            nft_contract_id,
+            token_id,
+            bidder_id,
+            deposit: U128(sealed_bid.deposit),
+            forfeited_to: sealed_bid.forfeit_to,
+        })
+        .emit();
+    }
+}
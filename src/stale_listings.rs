@@ -0,0 +1,106 @@
+use crate::*;
+
+// listings that can't be sold anymore, because their approval is gone or they've expired
+
+#[near_bindgen]
+impl Marketplace {
+    //Removes a listing the marketplace can no longer sell because the seller revoked its approval or moved the
+    //token. Anyone can call this. The approval is checked with the nft contract first, and if it's really gone the
+    //listing is cancelled (refunding any bid), its storage goes back to the seller and the caller is paid a small
+    //reward out of the seller's storage balance
+    pub fn remove_stale_listing(&mut self, nft_contract_id: AccountId, token_id: TokenId) -> Promise {
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        let listing = self
+            .listings
+            .get(&contract_and_token_id)
+            .expect("NFT not listed yet");
+        assert!(!listing.is_pending, "Purchase in progress");
+
+        self.internal_check_approval(&nft_contract_id, &token_id, listing.approval_id)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_APPROVAL)
+                    .resolve_stale_listing(
+                        nft_contract_id,
+                        token_id,
+                        listing.approval_id,
+                        env::predecessor_account_id(),
+                    ),
+            )
+    }
+
+    //Called back with the result of the nft_is_approved check made by remove_stale_listing. Returns whether the
+    //listing was removed
+    #[private]
+    pub fn resolve_stale_listing(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        approval_id: u64,
+        caller_id: AccountId,
+    ) -> bool {
+        if !self.internal_is_stale(&nft_contract_id, &token_id, approval_id) {
+            return false;
+        }
+        let listing = self.internal_cancel_listing(nft_contract_id.clone(), token_id.clone());
+
+        //the reward comes out of whatever the seller has paid for storage but isn't using anymore
+        let available = self
+            .internal_storage_balance_of(&listing.seller)
+            .map_or(0, |storage_balance| storage_balance.available.0);
+        let reward = STALE_LISTING_REWARD.min(available);
+        if reward > 0 {
+            let total = self.storage_deposits.get(&listing.seller).unwrap() - reward;
+            self.storage_deposits.insert(&listing.seller, &total);
+            Promise::new(caller_id.clone()).transfer(reward);
+        }
+
+        EventLogVariant::StaleListingRemoved(StaleListingLog {
+            seller: listing.seller,
+            nft_contract_id,
+            token_id,
+            caller_id,
+            reward: U128(reward),
+        })
+        .emit();
+        true
+    }
+
+    //Removes the fixed price listings in `listing_ids` that have expired, giving their storage back to the sellers.
+    //Anyone can call this, so the owner or a keeper can prune the market in batches. Listings that aren't there,
+    //haven't expired or are being bought are skipped. Returns how many were removed
+    pub fn prune_expired_listings(&mut self, listing_ids: Vec<ContractAndTokenId>) -> u32 {
+        let mut pruned = 0;
+        for listing_id in listing_ids {
+            let listing = match self.listings.get(&listing_id) {
+                Some(listing) if listing.is_expired() && !listing.is_pending => listing,
+                _ => continue,
+            };
+            self.internal_cancel_listing(listing.nft_contract_id.parse().unwrap(), listing.token_id);
+            pruned += 1;
+        }
+        pruned
+    }
+
+    //Called back with the result of the nft_is_approved check made after a bid is placed. If the marketplace lost
+    //its approval, the auction is called off and the escrowed bid refunded
+    #[private]
+    pub fn resolve_bid_approval(&mut self, nft_contract_id: AccountId, token_id: TokenId, approval_id: u64) {
+        if self.internal_is_stale(&nft_contract_id, &token_id, approval_id) {
+            self.internal_cancel_listing(nft_contract_id, token_id);
+        }
+    }
+}
+
+impl Marketplace {
+    //whether the nft_is_approved call we were called back with says the listing's approval is gone. Listings that
+    //have changed since the check (or are being bought) are left alone
+    fn internal_is_stale(&self, nft_contract_id: &AccountId, token_id: &TokenId, approval_id: u64) -> bool {
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        internal::promise_result_as_approval() == Some(false)
+            && self
+                .listings
+                .get(&contract_and_token_id)
+                .is_some_and(|listing| listing.approval_id == approval_id && !listing.is_pending)
+    }
+}
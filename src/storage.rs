@@ -0,0 +1,187 @@
+use crate::*;
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+
+// storage management (NEP-145)
+
+//accounts deposit NEAR to cover the storage their listings take up on the marketplace. Each listing is charged
+//against the seller's balance based on the bytes it actually uses, and released again once the listing is gone.
+#[near_bindgen]
+impl StorageManagement for Marketplace {
+    //Allows users to deposit storage. Optional account ID is so users can pay for storage for other people.
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        //get the account ID to pay for storage for. If we didn't specify one, we use the caller of the function
+        let storage_account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        //get the deposit value which is how much the user wants to add to their storage
+        let deposit = env::attached_deposit();
+        let registration_only = registration_only.unwrap_or(false);
+
+        let balance = match self.storage_deposits.get(&storage_account_id) {
+            //already registered accounts get the whole deposit back if they only wanted to register
+            Some(balance) if registration_only => {
+                if deposit > 0 {
+                    Promise::new(env::predecessor_account_id()).transfer(deposit);
+                }
+                balance
+            }
+            Some(balance) => balance + deposit,
+            None => {
+                //make sure the deposit is greater than or equal to the minimum storage balance
+                assert!(
+                    deposit >= MIN_STORAGE_BALANCE,
+                    "Requires minimum deposit of {}",
+                    MIN_STORAGE_BALANCE
+                );
+                //registering only keeps the minimum balance, the rest is refunded
+                if registration_only {
+                    let refund = deposit - MIN_STORAGE_BALANCE;
+                    if refund > 0 {
+                        Promise::new(env::predecessor_account_id()).transfer(refund);
+                    }
+                    MIN_STORAGE_BALANCE
+                } else {
+                    deposit
+                }
+            }
+        };
+
+        let previous_balance = self.storage_deposits.insert(&storage_account_id, &balance);
+        let amount = balance - previous_balance.unwrap_or(0);
+        if amount > 0 {
+            EventLogVariant::StorageDeposit(StorageLog {
+                account_id: storage_account_id.clone(),
+                amount: U128(amount),
+                total: U128(balance),
+            })
+            .emit();
+        }
+        //create the usage entry up front so charging a listing later doesn't grow the map
+        if self.storage_used.get(&storage_account_id).is_none() {
+            self.storage_used.insert(&storage_account_id, &0);
+        }
+
+        self.internal_storage_balance_of(&storage_account_id).unwrap()
+    }
+
+    //Allows users to withdraw any storage balance that their listings aren't using. Say Bob pays 0.01N and lists
+    //a token. Alice then buys it, so the bytes Bob's listing took up are released and Bob can withdraw them again.
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        //make sure the user attaches exactly 1 yoctoNEAR for security purposes.
+        //this will redirect them to the NEAR wallet (or requires a full access key).
+        assert_one_yocto();
+
+        //the account to withdraw storage to is always the function caller
+        let owner_id = env::predecessor_account_id();
+        let storage_balance = self
+            .internal_storage_balance_of(&owner_id)
+            .unwrap_or_else(|| panic!("The account {} is not registered", &owner_id));
+
+        //if no amount was specified, withdraw everything that isn't being used
+        let amount = amount.map(|amount| amount.0).unwrap_or(storage_balance.available.0);
+        assert!(
+            amount <= storage_balance.available.0,
+            "The amount is greater than the available storage balance"
+        );
+
+        if amount > 0 {
+            let total = storage_balance.total.0 - amount;
+            self.storage_deposits.insert(&owner_id, &total);
+            Promise::new(owner_id.clone()).transfer(amount);
+            EventLogVariant::StorageWithdraw(StorageLog {
+                account_id: owner_id.clone(),
+                amount: U128(amount),
+                total: U128(total),
+            })
+            .emit();
+        }
+
+        self.internal_storage_balance_of(&owner_id).unwrap()
+    }
+
+    //Unregisters the caller and returns their storage balance. With `force`, any listings they still have are
+    //cancelled first (refunding escrowed bids), otherwise unregistering with active listings panics. Offers, sealed
+    //bids and bid history entries (which are kept until the token is listed again) aren't cancelled, so unregistering
+    //panics while any of them are still stored.
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+
+        let owner_id = env::predecessor_account_id();
+        if self.storage_deposits.get(&owner_id).is_none() {
+            return false;
+        }
+
+        if let Some(by_owner_id) = self.by_owner_id.get(&owner_id) {
+            assert!(
+                force.unwrap_or(false),
+                "Can't unregister the account with active listings unless forced"
+            );
+            for contract_and_token_id in by_owner_id.to_vec() {
+                let listing = self.listings.get(&contract_and_token_id).expect("No listing");
+                //like cancel_listing, a sealed auction bidders have committed to has to run its course
+                assert!(
+                    self.sealed_bids.get(&contract_and_token_id).is_none(),
+                    "Sealed bids have been committed"
+                );
+                self.internal_cancel_listing(
+                    listing.nft_contract_id.parse().unwrap(),
+                    listing.token_id,
+                );
+            }
+        }
+
+        //whatever else the account still has stored is paid for by its balance, so it can't take that back
+        assert_eq!(
+            self.storage_used.get(&owner_id).unwrap_or(0),
+            0,
+            "Can't unregister the account while its offers or bids are still using storage"
+        );
+
+        let balance = self.storage_deposits.remove(&owner_id).unwrap_or(0);
+        self.storage_used.remove(&owner_id);
+        if balance > 0 {
+            Promise::new(owner_id.clone()).transfer(balance);
+            EventLogVariant::StorageWithdraw(StorageLog {
+                account_id: owner_id,
+                amount: U128(balance),
+                total: U128(0),
+            })
+            .emit();
+        }
+        true
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128(MIN_STORAGE_BALANCE),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.internal_storage_balance_of(&account_id)
+    }
+}
+
+impl Marketplace {
+    //internal method for getting an account's storage balance. Whatever its listings are using isn't available
+    pub(crate) fn internal_storage_balance_of(
+        &self,
+        account_id: &AccountId,
+    ) -> Option<StorageBalance> {
+        let total = self.storage_deposits.get(account_id)?;
+        let storage_used = self.storage_used.get(account_id).unwrap_or(0);
+        let used = Balance::from(storage_used) * env::storage_byte_cost();
+        Some(StorageBalance {
+            total: U128(total),
+            available: U128(total.saturating_sub(used)),
+        })
+    }
+}
@@ -1,15 +1,18 @@
 use crate::*;
 #[cfg(not(target_arch = "wasm32"))]
 #[cfg(test)]
-
 const MIN_REQUIRED_APPROVAL_YOCTO: u128 = 170000000000000000000;
 const MIN_REQUIRED_STORAGE_YOCTO: u128 = 10000000000000000000000;
 
 mod tests {
-    use near_sdk::test_utils::{accounts, VMContextBuilder};
-    use near_sdk::testing_env;
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::{accounts, get_created_receipts, get_logs, VMContextBuilder};
+    use near_sdk::{testing_env, PromiseResult, RuntimeFeesConfig, VMConfig};
 
     use super::*;
+    use near_contract_standards::non_fungible_token::approval::NonFungibleTokenApprovalReceiver;
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::storage_management::StorageManagement;
 
     // Allows for modifying the environment of the mocked blockchain
     fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
@@ -21,6 +24,61 @@ mod tests {
         builder
     }
 
+    // Lists an auction for `token_id` on the nft contract `accounts(4)`, sold by `accounts(0)`
+    fn add_auction(contract: &mut Marketplace, token_id: &str, starting_price: u128) {
+        let nft_contract_id = accounts(4);
+        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
+        let sale = Listing {
+            seller: accounts(0),
+            approval_id: 1,
+            nft_contract_id: nft_contract_id.to_string(),
+            token_id: token_id.to_string(),
+
+            starting_price,
+
+            ft_token_id: None,
+            end_at: 2_000,
+            started_at: 1_000,
+            highest_bidder: None,
+            highest_price: 0,
+            is_auction: true,
+            extension_window: 0,
+            extension_duration: 0,
+            reserve_price: 0,
+            min_increment: MinIncrement::default(),
+            buy_now_price: None,
+            dutch_auction: None,
+            sealed_auction: None,
+            is_pending: false,
+        };
+        contract.listings.insert(&contract_and_token_id, &sale);
+        let mut owner_token_set = UnorderedSet::new(b"o".to_vec());
+        owner_token_set.insert(&contract_and_token_id);
+        contract.by_owner_id.insert(&sale.seller, &owner_token_set);
+        let mut nft_token_set = UnorderedSet::new(b"n".to_vec());
+        nft_token_set.insert(&sale.token_id);
+        contract
+            .by_nft_contract_id
+            .insert(&nft_contract_id, &nft_token_set);
+
+        // bidders pay for the storage their bids take up in the bid history
+        register(contract, accounts(1));
+        register(contract, accounts(2));
+    }
+
+    // Returns the total amount of NEAR transferred to `account_id` by the receipts created so far
+    fn transferred_to(account_id: &AccountId) -> u128 {
+        get_created_receipts()
+            .iter()
+            .filter(|receipt| &receipt.receiver_id == account_id)
+            .flat_map(|receipt| receipt.actions.iter())
+            .map(|action| match action {
+                VmAction::Transfer { deposit } => *deposit,
+                _ => 0,
+            })
+            .sum()
+    }
+
     #[test]
     #[should_panic(expected = "Requires minimum deposit of 10000000000000000000000")]
     fn test_storage_deposit_insufficient_deposit() {
@@ -32,7 +90,7 @@ mod tests {
             .attached_deposit(MIN_REQUIRED_APPROVAL_YOCTO)
             .predecessor_account_id(accounts(0))
             .build());
-        contract.storage_deposit(Some(accounts(0)));
+        contract.storage_deposit(Some(accounts(0)), None);
     }
 
     #[test]
@@ -45,7 +103,7 @@ mod tests {
             .attached_deposit(MIN_REQUIRED_STORAGE_YOCTO)
             .predecessor_account_id(accounts(0))
             .build());
-        contract.storage_deposit(Some(accounts(0)));
+        contract.storage_deposit(Some(accounts(0)), None);
         let outcome = contract.storage_deposits.get(&accounts(0));
         let expected = MIN_REQUIRED_STORAGE_YOCTO;
         assert_eq!(outcome, Some(expected));
@@ -61,9 +119,10 @@ mod tests {
             .attached_deposit(MIN_REQUIRED_STORAGE_YOCTO)
             .predecessor_account_id(accounts(0))
             .build());
-        contract.storage_deposit(Some(accounts(0)));
-        let balance = contract.storage_balance_of(accounts(0));
-        assert_eq!(balance, U128(MIN_REQUIRED_STORAGE_YOCTO));
+        contract.storage_deposit(Some(accounts(0)), None);
+        let balance = contract.storage_balance_of(accounts(0)).unwrap();
+        assert_eq!(balance.total, U128(MIN_REQUIRED_STORAGE_YOCTO));
+        assert_eq!(balance.available, U128(MIN_REQUIRED_STORAGE_YOCTO));
     }
 
     #[test]
@@ -78,7 +137,7 @@ mod tests {
             .attached_deposit(MIN_REQUIRED_STORAGE_YOCTO)
             .predecessor_account_id(accounts(0))
             .build());
-        contract.storage_deposit(Some(accounts(0)));
+        contract.storage_deposit(Some(accounts(0)), None);
 
         // withdraw amount
         testing_env!(context
@@ -86,9 +145,9 @@ mod tests {
             .attached_deposit(U128(1).0) // below func requires a min of 1 yocto attached
             .predecessor_account_id(accounts(0))
             .build());
-        contract.storage_withdraw();
+        contract.storage_withdraw(None);
 
-        let remaining_amount = contract.storage_balance_of(accounts(0));
+        let remaining_amount = contract.storage_balance_of(accounts(0)).unwrap().total;
         assert_eq!(remaining_amount, U128(0))
     }
 
@@ -104,7 +163,7 @@ mod tests {
             .attached_deposit(MIN_REQUIRED_STORAGE_YOCTO)
             .predecessor_account_id(accounts(0))
             .build());
-        contract.storage_deposit(Some(accounts(0)));
+        contract.storage_deposit(Some(accounts(0)), None);
 
         // add sale
         let token_id = String::from("0n3C0ntr4ctT0Rul3Th3m4ll");
@@ -115,11 +174,21 @@ mod tests {
             token_id: token_id.clone(),                                 //the actual token ID
 
             starting_price: 0,
+
+            ft_token_id: None,
             end_at: 0,
             started_at: 0,
             highest_bidder: None,
             highest_price: 0,
             is_auction: false,
+            extension_window: 0,
+            extension_duration: 0,
+            reserve_price: 0,
+            min_increment: MinIncrement::default(),
+            buy_now_price: None,
+            dutch_auction: None,
+            sealed_auction: None,
+            is_pending: false,
         };
         let nft_contract_id = env::predecessor_account_id();
         let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
@@ -162,11 +231,10 @@ mod tests {
             .attached_deposit(MIN_REQUIRED_STORAGE_YOCTO)
             .predecessor_account_id(accounts(0))
             .build());
-        contract.storage_deposit(Some(accounts(0)));
+        contract.storage_deposit(Some(accounts(0)), None);
 
         // add sale
         let token_id = String::from("0n3C0ntr4ctT0Rul3Th3m4ll");
-        let nft_bid_yocto = U128(100);
         let sale = Listing {
             seller: accounts(0).clone(), //owner of the sale / token
             approval_id: U64(1).0,       //approval ID for that token that was given to the market
@@ -174,11 +242,21 @@ mod tests {
             token_id: token_id.clone(),                                 //the actual token ID
 
             starting_price: 0,
+
+            ft_token_id: None,
             end_at: 0,
             started_at: 0,
             highest_bidder: None,
             highest_price: 0,
             is_auction: false,
+            extension_window: 0,
+            extension_duration: 0,
+            reserve_price: 0,
+            min_increment: MinIncrement::default(),
+            buy_now_price: None,
+            dutch_auction: None,
+            sealed_auction: None,
+            is_pending: false,
         };
         let nft_contract_id = env::predecessor_account_id();
         let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
@@ -219,4 +297,2393 @@ mod tests {
         contract.purchase_nft(nft_contract_id, token_id);
         
     }
+
+    #[test]
+    fn test_bid_escrows_deposit_and_refunds_outbid() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+        let token_id = "auction";
+        add_auction(&mut contract, token_id, 100);
+
+        // first bid from bob
+        testing_env!(context
+            .block_timestamp(1_500)
+            .attached_deposit(100)
+            .signer_account_id(accounts(1))
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.bid(accounts(4), token_id.to_string());
+        assert_eq!(transferred_to(&accounts(1)), 0);
+
+        // charlie outbids bob, who gets his escrowed deposit back
+        testing_env!(context
+            .attached_deposit(150)
+            .signer_account_id(accounts(2))
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.bid(accounts(4), token_id.to_string());
+        assert_eq!(transferred_to(&accounts(1)), 100);
+
+        let sale = contract
+            .get_sale(format!("{}{}{}", accounts(4), DELIMETER, token_id))
+            .expect("No sale");
+        assert_eq!(sale.highest_bidder, Some(accounts(2)));
+        assert_eq!(sale.highest_price, 150);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid price")]
+    fn test_bid_below_highest_price() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+        let token_id = "auction";
+        add_auction(&mut contract, token_id, 100);
+
+        testing_env!(context
+            .block_timestamp(1_500)
+            .attached_deposit(150)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.bid(accounts(4), token_id.to_string());
+
+        testing_env!(context
+            .attached_deposit(150)
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.bid(accounts(4), token_id.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Auction not ended")]
+    fn test_settle_auction_before_end() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+        add_auction(&mut contract, "auction", 100);
+
+        testing_env!(context
+            .block_timestamp(1_500)
+            .predecessor_account_id(accounts(3))
+            .build());
+        contract.settle_auction(accounts(4), "auction".to_string());
+    }
+
+    #[test]
+    fn test_settle_auction_without_bids() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+        add_auction(&mut contract, "auction", 100);
+
+        // anyone can close the auction after it ends
+        testing_env!(context
+            .block_timestamp(2_000)
+            .predecessor_account_id(accounts(3))
+            .build());
+        contract.settle_auction(accounts(4), "auction".to_string());
+
+        assert_eq!(contract.get_supply_sales(), U64(0));
+        assert_eq!(contract.get_supply_by_owner_id(accounts(0)), U64(0));
+        assert_eq!(contract.get_supply_by_nft_contract_id(accounts(4)), U64(0));
+        assert!(get_created_receipts().is_empty());
+    }
+
+    #[test]
+    fn test_settle_auction_with_winning_bid() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+        add_auction(&mut contract, "auction", 100);
+
+        testing_env!(context
+            .block_timestamp(1_500)
+            .attached_deposit(200)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.bid(accounts(4), "auction".to_string());
+
+        testing_env!(context
+            .block_timestamp(2_500)
+            .attached_deposit(0)
+            .predecessor_account_id(accounts(3))
+            .build());
+        contract.settle_auction(accounts(4), "auction".to_string());
+
+        // the token is transferred to the winner, paid for by the escrowed bid
+        let receipts = get_created_receipts();
+        let transfer = receipts
+            .iter()
+            .find(|receipt| receipt.receiver_id == accounts(4))
+            .expect("No nft_transfer_payout call");
+        match &transfer.actions[0] {
+            VmAction::FunctionCall {
+                function_name,
+                args,
+                ..
+            } => {
+                assert_eq!(function_name, "nft_transfer_payout");
+                let args: serde_json::Value = serde_json::from_slice(args).unwrap();
+                assert_eq!(args["receiver_id"], accounts(1).to_string());
+                assert_eq!(args["balance"], "200");
+            }
+            _ => panic!("Expected a function call"),
+        }
+
+        // the bid history is kept once the sale has gone through, in case it's disputed
+        let resolves = function_calls("resolve_purchase");
+        let sale: Purchase = serde_json::from_value(resolves[0].1["purchase"].clone()).unwrap();
+        resolve_context(&context, PromiseResult::Successful(b"{\"payout\":{}}".to_vec()));
+        contract.resolve_purchase(sale);
+        let listing_id = format!("{}{}{}", accounts(4), DELIMETER, "auction");
+        assert!(contract.get_sale(listing_id.clone()).is_none());
+        assert_eq!(contract.get_bids(listing_id, None, None).len(), 1);
+    }
+
+    // A purchase of `token_id` from `seller`, paid in NEAR, with the 10% fee of `Marketplace::new(1000)`
+    fn purchase(
+        nft_contract_id: AccountId,
+        token_id: &str,
+        seller: AccountId,
+        buyer_id: AccountId,
+        price: u128,
+        deposit: u128,
+    ) -> Purchase {
+        Purchase {
+            nft_contract_id,
+            token_id: token_id.to_string(),
+            seller,
+            buyer_id,
+            price: U128(price),
+            marketplace_fee: U128(price / 10),
+            deposit: U128(deposit),
+            ft_token_id: None,
+        }
+    }
+
+    // Sets up the mocked blockchain as if `resolve_purchase` was called back with `promise_result`
+    fn resolve_context(context: &VMContextBuilder, promise_result: PromiseResult) {
+        testing_env!(
+            context.build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![promise_result],
+        );
+    }
+
+    #[test]
+    fn test_resolve_purchase_pays_royalties() {
+        let context = get_context(accounts(3));
+        testing_env!(context.build());
+        // 10% marketplace fee
+        let mut contract = Marketplace::new(1000);
+
+        // the nft contract splits the 900 left after fees between the creator and the seller
+        let payout = serde_json::json!({
+            "payout": { accounts(2).to_string(): "90", accounts(0).to_string(): "810" }
+        });
+        resolve_context(
+            &context,
+            PromiseResult::Successful(serde_json::to_vec(&payout).unwrap()),
+        );
+        let paid = contract.resolve_purchase(purchase(
+            accounts(4),
+            "token",
+            accounts(0),
+            accounts(1),
+            1000,
+            1000,
+        ));
+
+        assert_eq!(paid, U128(1000));
+        assert_eq!(transferred_to(&accounts(2)), 90);
+        assert_eq!(transferred_to(&accounts(0)), 810);
+        assert_eq!(transferred_to(&accounts(3)), 100);
+        assert_eq!(transferred_to(&accounts(1)), 0);
+    }
+
+    #[test]
+    fn test_resolve_purchase_ignores_oversized_payout() {
+        let context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(1000);
+
+        // a payout that tries to pay more than the price is ignored and the seller is paid in full
+        let payout = serde_json::json!({
+            "payout": { accounts(2).to_string(): "1000" }
+        });
+        resolve_context(
+            &context,
+            PromiseResult::Successful(serde_json::to_vec(&payout).unwrap()),
+        );
+        contract.resolve_purchase(purchase(
+            accounts(4),
+            "token",
+            accounts(0),
+            accounts(1),
+            1000,
+            1000,
+        ));
+
+        assert_eq!(transferred_to(&accounts(2)), 0);
+        assert_eq!(transferred_to(&accounts(0)), 900);
+        assert_eq!(transferred_to(&accounts(3)), 100);
+    }
+
+    #[test]
+    fn test_resolve_purchase_refunds_failed_transfer() {
+        let context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(1000);
+
+        resolve_context(&context, PromiseResult::Failed);
+        let paid = contract.resolve_purchase(purchase(
+            accounts(4),
+            "token",
+            accounts(0),
+            accounts(1),
+            1000,
+            1000,
+        ));
+
+        assert_eq!(paid, U128(0));
+        assert_eq!(transferred_to(&accounts(1)), 1000);
+        assert_eq!(transferred_to(&accounts(0)), 0);
+        assert_eq!(transferred_to(&accounts(3)), 0);
+    }
+
+    #[test]
+    fn test_failed_transfer_keeps_listing() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(1000);
+        add_auction(&mut contract, "auction", 100);
+
+        testing_env!(context
+            .block_timestamp(1_500)
+            .attached_deposit(200)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.bid(accounts(4), "auction".to_string());
+
+        // the listing is locked while the transfer is in flight
+        testing_env!(context
+            .block_timestamp(2_500)
+            .attached_deposit(0)
+            .predecessor_account_id(accounts(3))
+            .build());
+        contract.settle_auction(accounts(4), "auction".to_string());
+        let contract_and_token_id = format!("{}{}{}", accounts(4), DELIMETER, "auction");
+        assert!(contract.get_sale(contract_and_token_id.clone()).unwrap().is_pending);
+
+        // the nft contract panicked, so the winner is refunded and the listing stays up
+        resolve_context(&context, PromiseResult::Failed);
+        contract.resolve_purchase(purchase(
+            accounts(4),
+            "auction",
+            accounts(0),
+            accounts(1),
+            200,
+            200,
+        ));
+        assert_eq!(transferred_to(&accounts(1)), 200);
+        let sale = contract.get_sale(contract_and_token_id).expect("No sale");
+        assert!(!sale.is_pending);
+        assert_eq!(sale.highest_bidder, None);
+        assert_eq!(contract.get_supply_by_owner_id(accounts(0)), U64(1));
+    }
+
+    #[test]
+    fn test_successful_transfer_removes_listing() {
+        let context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(1000);
+        add_auction(&mut contract, "auction", 100);
+
+        resolve_context(&context, PromiseResult::Successful(b"{\"payout\":{}}".to_vec()));
+        contract.resolve_purchase(purchase(
+            accounts(4),
+            "auction",
+            accounts(0),
+            accounts(1),
+            200,
+            200,
+        ));
+        assert_eq!(contract.get_supply_sales(), U64(0));
+        assert_eq!(contract.get_supply_by_owner_id(accounts(0)), U64(0));
+        assert_eq!(contract.get_supply_by_nft_contract_id(accounts(4)), U64(0));
+        assert_eq!(transferred_to(&accounts(0)), 180);
+        assert_eq!(transferred_to(&accounts(3)), 20);
+        assert_eq!(transferred_to(&accounts(1)), 0);
+    }
+
+    #[test]
+    fn test_purchase_refunds_overpayment() {
+        let mut context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(1000);
+        add_auction(&mut contract, "token", 0);
+        let contract_and_token_id = format!("{}{}{}", accounts(4), DELIMETER, "token");
+        let mut sale = contract.listings.get(&contract_and_token_id).unwrap();
+        sale.is_auction = false;
+        sale.starting_price = 1000;
+        contract.listings.insert(&contract_and_token_id, &sale);
+
+        // bought through another contract, so the deposit comes from the predecessor rather than the signer
+        testing_env!(context
+            .attached_deposit(1500)
+            .signer_account_id(accounts(2))
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.purchase_nft(accounts(4), "token".to_string());
+
+        // the sale is made at the listing price, not the attached deposit
+        let receipts = get_created_receipts();
+        let resolve = receipts
+            .iter()
+            .flat_map(|receipt| receipt.actions.iter())
+            .find_map(|action| match action {
+                VmAction::FunctionCall {
+                    function_name,
+                    args,
+                    ..
+                } if function_name == "resolve_purchase_approval" => {
+                    Some(serde_json::from_slice::<serde_json::Value>(args).unwrap())
+                }
+                _ => None,
+            })
+            .expect("No resolve_purchase_approval call");
+        assert_eq!(resolve["purchase"]["buyer_id"], accounts(1).to_string());
+        assert_eq!(resolve["purchase"]["price"], "1000");
+        assert_eq!(resolve["purchase"]["deposit"], "1500");
+
+        resolve_context(&context, PromiseResult::Successful(b"{\"payout\":{}}".to_vec()));
+        contract.resolve_purchase(purchase(
+            accounts(4),
+            "token",
+            accounts(0),
+            accounts(1),
+            1000,
+            1500,
+        ));
+        assert_eq!(transferred_to(&accounts(1)), 500);
+        assert_eq!(transferred_to(&accounts(0)), 900);
+        assert_eq!(transferred_to(&accounts(3)), 100);
+    }
+
+    // Context for `nft_approve` on the nft contract `accounts(4)` calling back into the marketplace
+    fn approve_context(owner_id: AccountId) -> VMContextBuilder {
+        let mut context = get_context(owner_id);
+        context
+            .predecessor_account_id(accounts(4))
+            .block_timestamp(1_000);
+        context
+    }
+
+    // Pays the minimum storage deposit for `account_id`
+    fn register(contract: &mut Marketplace, account_id: AccountId) {
+        testing_env!(get_context(account_id.clone())
+            .attached_deposit(MIN_REQUIRED_STORAGE_YOCTO)
+            .build());
+        contract.storage_deposit(Some(account_id), None);
+    }
+
+    #[test]
+    fn test_nft_on_approve_fixed_price() {
+        let mut context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+
+        register(&mut contract, accounts(0));
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            "token".to_string(),
+            accounts(0),
+            7,
+            r#"{"sale_type": "fixed_price", "price": "500"}"#.to_string(),
+        );
+
+        let sale = contract
+            .get_sale(format!("{}{}{}", accounts(4), DELIMETER, "token"))
+            .expect("No sale");
+        assert_eq!(sale.seller, accounts(0));
+        assert_eq!(sale.approval_id, 7);
+        assert_eq!(sale.starting_price, 500);
+        assert!(!sale.is_auction);
+        assert_eq!(contract.get_supply_by_owner_id(accounts(0)), U64(1));
+        assert_eq!(contract.get_supply_by_nft_contract_id(accounts(4)), U64(1));
+
+        // a free listing can't be bought with nothing attached
+        testing_env!(context
+            .attached_deposit(0)
+            .signer_account_id(accounts(1))
+            .predecessor_account_id(accounts(1))
+            .build());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.purchase_nft(accounts(4), "token".to_string())
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_nft_on_approve_auction() {
+        let context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+
+        register(&mut contract, accounts(0));
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            "token".to_string(),
+            accounts(0),
+            1,
+            r#"{"sale_type": "auction", "starting_price": "100", "end_at": "5000"}"#.to_string(),
+        );
+
+        let sale = contract
+            .get_sale(format!("{}{}{}", accounts(4), DELIMETER, "token"))
+            .expect("No sale");
+        assert!(sale.is_auction);
+        assert_eq!(sale.starting_price, 100);
+        assert_eq!(sale.started_at, 1_000);
+        assert_eq!(sale.end_at, 5_000);
+        assert_eq!(sale.extension_window, contract.get_auction_extension().window.0);
+        assert_eq!(sale.extension_duration, contract.get_auction_extension().duration.0);
+
+        // sellers can pick their own extension instead of the market's
+        register(&mut contract, accounts(0));
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            "other".to_string(),
+            accounts(0),
+            2,
+            r#"{"sale_type": "auction", "starting_price": "100", "end_at": "5000", "extension_window": "0"}"#
+                .to_string(),
+        );
+        let sale = contract
+            .get_sale(format!("{}{}{}", accounts(4), DELIMETER, "other"))
+            .expect("No sale");
+        assert_eq!(sale.extension_window, 0);
+        assert_eq!(sale.extension_duration, contract.get_auction_extension().duration.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid sale terms in msg")]
+    fn test_nft_on_approve_malformed_msg() {
+        let context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+
+        register(&mut contract, accounts(0));
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve("token".to_string(), accounts(0), 1, "".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "end_at must be after started_at")]
+    fn test_nft_on_approve_auction_ends_before_start() {
+        let context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+
+        register(&mut contract, accounts(0));
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            "token".to_string(),
+            accounts(0),
+            1,
+            r#"{"sale_type": "auction", "starting_price": "100", "started_at": "6000", "end_at": "5000"}"#
+                .to_string(),
+        );
+    }
+
+    #[test]
+    fn test_listing_storage_is_charged_and_released() {
+        let context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+        register(&mut contract, accounts(0));
+
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            "token".to_string(),
+            accounts(0),
+            1,
+            r#"{"sale_type": "fixed_price", "price": "500"}"#.to_string(),
+        );
+
+        // the bytes the listing takes up are no longer available to withdraw
+        let balance = contract.storage_balance_of(accounts(0)).unwrap();
+        assert_eq!(balance.total, U128(MIN_REQUIRED_STORAGE_YOCTO));
+        assert!(balance.available.0 < MIN_REQUIRED_STORAGE_YOCTO);
+        assert!(contract.storage_used.get(&accounts(0)).unwrap() > 0);
+
+        // cancelling the listing frees them up again
+        testing_env!(get_context(accounts(0)).build());
+        contract.cancel_listing(accounts(4), "token".to_string());
+        let balance = contract.storage_balance_of(accounts(0)).unwrap();
+        assert_eq!(balance.available, U128(MIN_REQUIRED_STORAGE_YOCTO));
+    }
+
+    #[test]
+    fn test_cancel_listing_through_another_contract() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(10);
+        add_expiring_listing(&mut contract, "token");
+        let listing_id = format!("{}{}{}", accounts(4), DELIMETER, "token");
+
+        // a contract the seller calls can't take their listing down
+        testing_env!(get_context(accounts(2))
+            .signer_account_id(accounts(0))
+            .build());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.cancel_listing(accounts(4), "token".to_string())
+        }));
+        assert!(result.is_err());
+        assert!(contract.get_sale(listing_id.clone()).is_some());
+
+        // but the seller can, even through a contract of their own
+        testing_env!(get_context(accounts(0))
+            .signer_account_id(accounts(1))
+            .build());
+        contract.cancel_listing(accounts(4), "token".to_string());
+        assert!(contract.get_sale(listing_id).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Insufficient storage paid")]
+    fn test_nft_on_approve_without_storage() {
+        let context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            "token".to_string(),
+            accounts(0),
+            1,
+            r#"{"sale_type": "fixed_price", "price": "500"}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_storage_deposit_registration_only() {
+        let context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+
+        testing_env!(get_context(accounts(0))
+            .attached_deposit(MIN_REQUIRED_STORAGE_YOCTO * 2)
+            .build());
+        let balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(balance.total, U128(MIN_REQUIRED_STORAGE_YOCTO));
+        assert_eq!(transferred_to(&accounts(0)), MIN_REQUIRED_STORAGE_YOCTO);
+        assert_eq!(
+            contract.storage_balance_bounds().min,
+            U128(MIN_REQUIRED_STORAGE_YOCTO)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't unregister the account with active listings")]
+    fn test_storage_unregister_with_listings() {
+        let context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+        register(&mut contract, accounts(0));
+
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            "token".to_string(),
+            accounts(0),
+            1,
+            r#"{"sale_type": "fixed_price", "price": "500"}"#.to_string(),
+        );
+
+        testing_env!(get_context(accounts(0)).attached_deposit(1).build());
+        contract.storage_unregister(None);
+    }
+
+    // Parses the NEP-297 events logged so far
+    fn events() -> Vec<serde_json::Value> {
+        get_logs()
+            .iter()
+            .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+            .map(|event| serde_json::from_str(event).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_bid_events() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+        add_auction(&mut contract, "auction", 100);
+
+        testing_env!(context
+            .block_timestamp(1_500)
+            .attached_deposit(100)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.bid(accounts(4), "auction".to_string());
+        testing_env!(context
+            .attached_deposit(150)
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.bid(accounts(4), "auction".to_string());
+
+        let events = events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["standard"], EVENT_STANDARD);
+        assert_eq!(events[0]["version"], EVENT_VERSION);
+        assert_eq!(events[0]["event"], "outbid_refund");
+        assert_eq!(events[0]["data"]["bidder_id"], accounts(1).to_string());
+        assert_eq!(events[0]["data"]["amount"], "100");
+        assert_eq!(events[1]["event"], "bid_placed");
+        assert_eq!(events[1]["data"]["bidder_id"], accounts(2).to_string());
+        assert_eq!(events[1]["data"]["amount"], "150");
+    }
+
+    #[test]
+    fn test_sale_completed_event() {
+        let context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(1000);
+
+        let payout = serde_json::json!({
+            "payout": { accounts(2).to_string(): "90", accounts(0).to_string(): "800" }
+        });
+        resolve_context(
+            &context,
+            PromiseResult::Successful(serde_json::to_vec(&payout).unwrap()),
+        );
+        contract.resolve_purchase(purchase(
+            accounts(4),
+            "token",
+            accounts(0),
+            accounts(1),
+            1000,
+            1200,
+        ));
+
+        let events = events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["event"], "sale_completed");
+        let data = &events[0]["data"];
+        assert_eq!(data["buyer_id"], accounts(1).to_string());
+        assert_eq!(data["price"], "1000");
+        assert_eq!(data["marketplace_fee"], "100");
+        assert_eq!(data["refund"], "200");
+        // the 10 the payout left over goes to the seller
+        assert_eq!(data["payout"][accounts(0).to_string()], "810");
+        assert_eq!(data["payout"][accounts(2).to_string()], "90");
+    }
+
+    // Returns the args of every `function_name` call made by the receipts created so far
+    fn function_calls(function_name: &str) -> Vec<(AccountId, serde_json::Value)> {
+        get_created_receipts()
+            .iter()
+            .flat_map(|receipt| {
+                receipt.actions.iter().filter_map(move |action| match action {
+                    VmAction::FunctionCall {
+                        function_name: name,
+                        args,
+                        ..
+                    } if name == function_name => Some((
+                        receipt.receiver_id.clone(),
+                        serde_json::from_slice(args).unwrap(),
+                    )),
+                    _ => None,
+                })
+            })
+            .collect()
+    }
+
+    // Lists `token_id` for a fixed price of 1000 `accounts(5)` tokens
+    fn add_ft_listing(contract: &mut Marketplace, token_id: &str) {
+        testing_env!(get_context(accounts(3)).build());
+        contract.add_ft_token_ids(vec![accounts(5)]);
+        register(contract, accounts(0));
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            token_id.to_string(),
+            accounts(0),
+            1,
+            format!(
+                r#"{{"sale_type": "fixed_price", "price": "1000", "ft_token_id": "{}"}}"#,
+                accounts(5)
+            ),
+        );
+    }
+
+    #[test]
+    fn test_ft_on_transfer_purchase() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        add_ft_listing(&mut contract, "token");
+        assert_eq!(contract.get_supported_ft_token_ids(), vec![accounts(5)]);
+
+        // the fungible token contract calls us after the buyer's ft_transfer_call
+        testing_env!(get_context(accounts(5)).build());
+        let unused = contract.ft_on_transfer(
+            accounts(1),
+            U128(1200),
+            format!(
+                r#"{{"action": "purchase", "nft_contract_id": "{}", "token_id": "token"}}"#,
+                accounts(4)
+            ),
+        );
+        // the 200 sent on top of the price goes back through ft_resolve_transfer
+        match unused {
+            PromiseOrValue::Value(unused) => assert_eq!(unused, U128(200)),
+            _ => panic!("Expected a value"),
+        }
+        // the approval is checked before the nft is transferred
+        let approvals = function_calls("nft_is_approved");
+        assert_eq!(approvals[0].0, accounts(4));
+        assert_eq!(approvals[0].1["approval_id"], 1);
+        let resolves = function_calls("resolve_purchase_approval");
+        let ft_purchase: Purchase = serde_json::from_value(resolves[0].1["purchase"].clone()).unwrap();
+        resolve_context(&get_context(accounts(3)), PromiseResult::Successful(b"true".to_vec()));
+        contract.resolve_purchase_approval(ft_purchase, 1);
+        let transfers = function_calls("nft_transfer_payout");
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(transfers[0].1["receiver_id"], accounts(1).to_string());
+
+        // once the nft is transferred everyone is paid in the fungible token
+        resolve_context(
+            &get_context(accounts(3)),
+            PromiseResult::Successful(b"{\"payout\":{}}".to_vec()),
+        );
+        let mut ft_purchase = purchase(accounts(4), "token", accounts(0), accounts(1), 1000, 1000);
+        ft_purchase.ft_token_id = Some(accounts(5));
+        contract.resolve_purchase(ft_purchase);
+
+        let payouts = function_calls("ft_transfer");
+        assert_eq!(payouts.len(), 2);
+        assert!(payouts.iter().all(|(ft_token_id, _)| ft_token_id == &accounts(5)));
+        assert!(payouts.iter().any(|(_, args)| {
+            args["receiver_id"] == accounts(0).to_string() && args["amount"] == "900"
+        }));
+        assert!(payouts.iter().any(|(_, args)| {
+            args["receiver_id"] == accounts(3).to_string() && args["amount"] == "100"
+        }));
+        assert_eq!(transferred_to(&accounts(0)), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Listing is not priced in NEAR")]
+    fn test_purchase_ft_listing_with_near() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        add_ft_listing(&mut contract, "token");
+
+        testing_env!(get_context(accounts(1)).attached_deposit(1000).build());
+        contract.purchase_nft(accounts(4), "token".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "is not an accepted fungible token")]
+    fn test_ft_on_transfer_unknown_token() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        add_ft_listing(&mut contract, "token");
+
+        testing_env!(get_context(accounts(2)).build());
+        contract.ft_on_transfer(
+            accounts(1),
+            U128(1000),
+            format!(
+                r#"{{"action": "purchase", "nft_contract_id": "{}", "token_id": "token"}}"#,
+                accounts(4)
+            ),
+        );
+    }
+
+    // `buyer_id` offers `amount` for `token_id` on the nft contract `accounts(4)`, until timestamp 5000
+    fn make_offer(contract: &mut Marketplace, buyer_id: AccountId, token_id: &str, amount: u128) {
+        register(contract, buyer_id.clone());
+        testing_env!(get_context(buyer_id)
+            .attached_deposit(amount)
+            .block_timestamp(1_000)
+            .build());
+        contract.make_offer(accounts(4), token_id.to_string(), U64(5_000));
+    }
+
+    #[test]
+    fn test_accept_offer() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        make_offer(&mut contract, accounts(1), "token", 800);
+        assert_eq!(contract.get_offers(accounts(4), "token".to_string()).len(), 1);
+        assert!(contract.storage_used.get(&accounts(1)).unwrap() > 0);
+
+        // the owner accepts by approving the marketplace, the token doesn't have to be listed
+        testing_env!(approve_context(accounts(0)).build());
+        match contract.nft_on_approve(
+            "token".to_string(),
+            accounts(0),
+            3,
+            format!(r#"{{"sale_type": "accept_offer", "buyer_id": "{}"}}"#, accounts(1)),
+        ) {
+            PromiseOrValue::Promise(_) => {}
+            _ => panic!("Expected a promise"),
+        }
+
+        let transfers = function_calls("nft_transfer_payout");
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(transfers[0].0, accounts(4));
+        assert_eq!(transfers[0].1["receiver_id"], accounts(1).to_string());
+        assert_eq!(transfers[0].1["approval_id"], 3);
+        assert_eq!(transfers[0].1["balance"], "720");
+        let resolves = function_calls("resolve_purchase");
+        assert_eq!(resolves[0].1["purchase"]["seller"], accounts(0).to_string());
+        assert_eq!(resolves[0].1["purchase"]["price"], "800");
+
+        // the offer is used up and the buyer gets their storage back
+        assert!(contract.get_offers(accounts(4), "token".to_string()).is_empty());
+        assert_eq!(contract.storage_used.get(&accounts(1)), Some(0));
+        let events = events();
+        assert_eq!(events.last().unwrap()["event"], "offer_accepted");
+        assert_eq!(events.last().unwrap()["data"]["amount"], "800");
+    }
+
+    #[test]
+    #[should_panic(expected = "Offer has expired")]
+    fn test_accept_expired_offer() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        make_offer(&mut contract, accounts(1), "token", 800);
+
+        testing_env!(approve_context(accounts(0)).block_timestamp(5_000).build());
+        contract.nft_on_approve(
+            "token".to_string(),
+            accounts(0),
+            3,
+            format!(r#"{{"sale_type": "accept_offer", "buyer_id": "{}"}}"#, accounts(1)),
+        );
+    }
+
+    #[test]
+    fn test_accept_offer_removes_previous_owners_listing() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        add_expiring_listing(&mut contract, "token");
+        make_offer(&mut contract, accounts(1), "token", 800);
+
+        // the token has moved to accounts(5), whose approval can't be put on accounts(0)'s listing
+        testing_env!(approve_context(accounts(5)).build());
+        contract.nft_on_approve(
+            "token".to_string(),
+            accounts(5),
+            3,
+            format!(r#"{{"sale_type": "accept_offer", "buyer_id": "{}"}}"#, accounts(1)),
+        );
+        assert!(contract
+            .get_sale(format!("{}{}{}", accounts(4), DELIMETER, "token"))
+            .is_none());
+        assert_eq!(contract.storage_used.get(&accounts(0)), Some(0));
+        let resolves = function_calls("resolve_purchase");
+        assert_eq!(resolves[0].1["purchase"]["seller"], accounts(5).to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Sealed bids have been committed")]
+    fn test_accept_offer_during_sealed_auction() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        add_sealed_auction(&mut contract, "token", r#", "unrevealed_bids": "forfeit""#);
+        commit_bid(&mut contract, accounts(2), 500, "salt", 900);
+        make_offer(&mut contract, accounts(1), "token", 800);
+
+        // selling the token would leave the committed bids with no listing to be revealed in
+        testing_env!(approve_context(accounts(0)).block_timestamp(1_600).build());
+        contract.nft_on_approve(
+            "token".to_string(),
+            accounts(0),
+            3,
+            format!(r#"{{"sale_type": "accept_offer", "buyer_id": "{}"}}"#, accounts(1)),
+        );
+    }
+
+    #[test]
+    fn test_withdraw_offer() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        make_offer(&mut contract, accounts(1), "token", 800);
+
+        // a new offer replaces the old one and refunds it
+        testing_env!(get_context(accounts(1))
+            .attached_deposit(900)
+            .block_timestamp(1_000)
+            .build());
+        contract.make_offer(accounts(4), "token".to_string(), U64(5_000));
+        assert_eq!(transferred_to(&accounts(1)), 800);
+        let offers = contract.get_offers(accounts(4), "token".to_string());
+        assert_eq!(offers.len(), 1);
+        assert_eq!(offers[0].amount, 900);
+
+        testing_env!(get_context(accounts(1)).attached_deposit(1).build());
+        contract.withdraw_offer(accounts(4), "token".to_string());
+        assert_eq!(transferred_to(&accounts(1)), 900);
+        assert!(contract.get_offers(accounts(4), "token".to_string()).is_empty());
+        assert_eq!(events()[0]["event"], "offer_withdrawn");
+    }
+
+    #[test]
+    fn test_refund_expired_offer() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        make_offer(&mut contract, accounts(1), "token", 800);
+
+        // nobody can refund an offer that can still be accepted
+        testing_env!(get_context(accounts(2)).block_timestamp(4_999).build());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.refund_expired_offer(accounts(4), "token".to_string(), accounts(1))
+        }));
+        assert!(result.is_err());
+
+        testing_env!(get_context(accounts(2)).block_timestamp(5_000).build());
+        contract.refund_expired_offer(accounts(4), "token".to_string(), accounts(1));
+        assert_eq!(transferred_to(&accounts(1)), 800);
+        assert!(contract.get_offers(accounts(4), "token".to_string()).is_empty());
+        assert_eq!(events()[0]["event"], "offer_expired");
+    }
+
+    // Context for the owner of `token_id` filling `accounts(1)`'s collection offer on the nft contract `accounts(4)`
+    fn fill_collection_offer(contract: &mut Marketplace, owner_id: AccountId, token_id: &str) {
+        testing_env!(approve_context(owner_id.clone()).build());
+        contract.nft_on_approve(
+            token_id.to_string(),
+            owner_id,
+            1,
+            format!(r#"{{"sale_type": "fill_collection_offer", "buyer_id": "{}"}}"#, accounts(1)),
+        );
+    }
+
+    #[test]
+    fn test_collection_offer_fills_up_to_quantity() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        register(&mut contract, accounts(1));
+        testing_env!(get_context(accounts(1)).attached_deposit(600).build());
+        contract.make_collection_offer(accounts(4), U128(300), 2);
+        assert_eq!(contract.get_collection_offers(accounts(4)).len(), 1);
+
+        // two different holders each sell one token to the offer
+        fill_collection_offer(&mut contract, accounts(0), "first");
+        let transfers = function_calls("nft_transfer_payout");
+        assert_eq!(transfers[0].1["token_id"], "first");
+        assert_eq!(transfers[0].1["receiver_id"], accounts(1).to_string());
+        assert_eq!(transfers[0].1["balance"], "270");
+        let offers = contract.get_collection_offers(accounts(4));
+        assert_eq!(offers[0].quantity, 1);
+        let event = &events()[0];
+        assert_eq!(event["event"], "collection_offer_filled");
+        assert_eq!(event["data"]["token_id"], "first");
+        assert_eq!(event["data"]["quantity"], 1);
+
+        fill_collection_offer(&mut contract, accounts(2), "second");
+        let resolves = function_calls("resolve_purchase");
+        assert_eq!(resolves[0].1["purchase"]["seller"], accounts(2).to_string());
+        assert_eq!(resolves[0].1["purchase"]["price"], "300");
+        assert!(contract.get_collection_offers(accounts(4)).is_empty());
+        assert_eq!(contract.storage_used.get(&accounts(1)), Some(0));
+
+        // the offer is used up
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            fill_collection_offer(&mut contract, accounts(0), "third")
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Attached deposit must be exactly 900")]
+    fn test_collection_offer_must_escrow_every_token() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        register(&mut contract, accounts(1));
+        testing_env!(get_context(accounts(1)).attached_deposit(300).build());
+        contract.make_collection_offer(accounts(4), U128(300), 3);
+    }
+
+    #[test]
+    fn test_ft_collection_offer() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        contract.add_ft_token_ids(vec![accounts(5)]);
+        register(&mut contract, accounts(1));
+
+        testing_env!(get_context(accounts(5)).build());
+        let unused = contract.ft_on_transfer(
+            accounts(1),
+            U128(1000),
+            format!(
+                r#"{{"action": "collection_offer", "nft_contract_id": "{}", "price": "300", "quantity": 3}}"#,
+                accounts(4)
+            ),
+        );
+        match unused {
+            PromiseOrValue::Value(unused) => assert_eq!(unused, U128(100)),
+            _ => panic!("Expected a value"),
+        }
+
+        fill_collection_offer(&mut contract, accounts(0), "token");
+        let resolves = function_calls("resolve_purchase");
+        assert_eq!(resolves[0].1["purchase"]["ft_token_id"], accounts(5).to_string());
+
+        // withdrawing refunds the two tokens left in the fungible token
+        testing_env!(get_context(accounts(1)).attached_deposit(1).build());
+        contract.withdraw_collection_offer(accounts(4));
+        let refunds = function_calls("ft_transfer");
+        assert_eq!(refunds.len(), 1);
+        assert_eq!(refunds[0].0, accounts(5));
+        assert_eq!(refunds[0].1["receiver_id"], accounts(1).to_string());
+        assert_eq!(refunds[0].1["amount"], "600");
+        assert!(contract.get_collection_offers(accounts(4)).is_empty());
+    }
+
+    #[test]
+    fn test_late_bid_extends_auction() {
+        let mut context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(1000);
+        add_auction(&mut contract, "auction", 100);
+        let contract_and_token_id = format!("{}{}{}", accounts(4), DELIMETER, "auction");
+        let mut sale = contract.listings.get(&contract_and_token_id).unwrap();
+        sale.extension_window = 200;
+        sale.extension_duration = 300;
+        contract.listings.insert(&contract_and_token_id, &sale);
+
+        // a bid well before the end leaves it alone
+        testing_env!(context
+            .block_timestamp(1_500)
+            .attached_deposit(100)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.bid(accounts(4), "auction".to_string());
+        assert_eq!(contract.listings.get(&contract_and_token_id).unwrap().end_at, 2_000);
+        assert_eq!(events()[0]["data"]["end_at"], "2000");
+
+        // a bid inside the window pushes the end back
+        testing_env!(context
+            .block_timestamp(1_850)
+            .attached_deposit(150)
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.bid(accounts(4), "auction".to_string());
+        assert_eq!(contract.listings.get(&contract_and_token_id).unwrap().end_at, 2_300);
+        assert_eq!(events()[1]["event"], "bid_placed");
+        assert_eq!(events()[1]["data"]["end_at"], "2300");
+
+        testing_env!(context.block_timestamp(2_000).build());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.settle_auction(accounts(4), "auction".to_string())
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn test_set_auction_extension_only_owner() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.set_auction_extension(U64(0), U64(0));
+    }
+
+    #[test]
+    fn test_bid_min_increment() {
+        let mut context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(1000);
+        add_auction(&mut contract, "auction", 100);
+        let contract_and_token_id = format!("{}{}{}", accounts(4), DELIMETER, "auction");
+        let mut sale = contract.listings.get(&contract_and_token_id).unwrap();
+        // every bid has to raise the last one by 10%
+        sale.min_increment = MinIncrement::BasisPoints(1000);
+        contract.listings.insert(&contract_and_token_id, &sale);
+
+        testing_env!(context
+            .block_timestamp(1_500)
+            .attached_deposit(100)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.bid(accounts(4), "auction".to_string());
+
+        testing_env!(context
+            .attached_deposit(109)
+            .predecessor_account_id(accounts(2))
+            .build());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.bid(accounts(4), "auction".to_string())
+        }));
+        assert!(result.is_err());
+
+        testing_env!(context.attached_deposit(110).build());
+        contract.bid(accounts(4), "auction".to_string());
+        let sale = contract.listings.get(&contract_and_token_id).unwrap();
+        assert_eq!(sale.highest_bidder, Some(accounts(2)));
+        assert_eq!(sale.highest_price, 110);
+
+        assert_eq!(MinIncrement::Absolute(U128(50)).min_bid(110), 160);
+        assert_eq!(MinIncrement::Absolute(U128(0)).min_bid(110), 111);
+    }
+
+    #[test]
+    fn test_settle_auction_below_reserve() {
+        let mut context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(1000);
+        add_auction(&mut contract, "auction", 100);
+        let contract_and_token_id = format!("{}{}{}", accounts(4), DELIMETER, "auction");
+        let mut sale = contract.listings.get(&contract_and_token_id).unwrap();
+        sale.reserve_price = 500;
+        contract.listings.insert(&contract_and_token_id, &sale);
+
+        // the reserve isn't shown to bidders
+        let view = serde_json::to_value(contract.get_sale(contract_and_token_id.clone())).unwrap();
+        assert!(view.get("reserve_price").is_none());
+
+        testing_env!(context
+            .block_timestamp(1_500)
+            .attached_deposit(300)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.bid(accounts(4), "auction".to_string());
+
+        // the auction ends without a sale and the top bid goes back
+        testing_env!(context.block_timestamp(2_000).attached_deposit(0).build());
+        match contract.settle_auction(accounts(4), "auction".to_string()) {
+            PromiseOrValue::Value(paid) => assert_eq!(paid, U128(0)),
+            _ => panic!("Expected a value"),
+        }
+        assert!(function_calls("nft_transfer_payout").is_empty());
+        assert_eq!(transferred_to(&accounts(1)), 300);
+        assert_eq!(contract.get_supply_sales(), U64(0));
+        assert_eq!(events()[0]["event"], "listing_cancelled");
+    }
+
+    #[test]
+    fn test_buy_now_ends_auction() {
+        let mut context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(1000);
+        add_auction(&mut contract, "auction", 100);
+        let contract_and_token_id = format!("{}{}{}", accounts(4), DELIMETER, "auction");
+        let mut sale = contract.listings.get(&contract_and_token_id).unwrap();
+        sale.buy_now_price = Some(1000);
+        contract.listings.insert(&contract_and_token_id, &sale);
+
+        testing_env!(context
+            .block_timestamp(1_500)
+            .attached_deposit(300)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.bid(accounts(4), "auction".to_string());
+
+        // buying outright refunds the highest bid and settles at the buy now price
+        testing_env!(context
+            .attached_deposit(1200)
+            .signer_account_id(accounts(2))
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.purchase_nft(accounts(4), "auction".to_string());
+        assert_eq!(transferred_to(&accounts(1)), 300);
+        assert_eq!(events()[0]["event"], "outbid_refund");
+        let resolves = function_calls("resolve_purchase_approval");
+        assert_eq!(resolves[0].1["purchase"]["buyer_id"], accounts(2).to_string());
+        assert_eq!(resolves[0].1["purchase"]["price"], "1000");
+        assert_eq!(resolves[0].1["purchase"]["deposit"], "1200");
+
+        let sale = contract.listings.get(&contract_and_token_id).unwrap();
+        assert!(sale.is_pending);
+        assert_eq!(sale.highest_bidder, None);
+
+        // nobody else can buy it while the purchase is going through
+        testing_env!(context
+            .attached_deposit(2000)
+            .predecessor_account_id(accounts(1))
+            .build());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.purchase_nft(accounts(4), "auction".to_string())
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Auction has ended")]
+    fn test_buy_now_after_auction_ends() {
+        let mut context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(1000);
+        add_auction(&mut contract, "auction", 100);
+        let contract_and_token_id = format!("{}{}{}", accounts(4), DELIMETER, "auction");
+        let mut sale = contract.listings.get(&contract_and_token_id).unwrap();
+        sale.buy_now_price = Some(1000);
+        contract.listings.insert(&contract_and_token_id, &sale);
+
+        testing_env!(context
+            .block_timestamp(2_000)
+            .attached_deposit(1000)
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.purchase_nft(accounts(4), "auction".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Auctions are settled with settle_auction")]
+    fn test_purchase_auction_without_buy_now() {
+        let mut context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(1000);
+        add_auction(&mut contract, "auction", 100);
+
+        testing_env!(context
+            .block_timestamp(1_500)
+            .attached_deposit(1000)
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.purchase_nft(accounts(4), "auction".to_string());
+    }
+
+    // Lists `token_id` as a dutch auction falling from 1000 to 200 between 1000 and 2000
+    fn add_dutch_auction(contract: &mut Marketplace, token_id: &str, curve: &str) {
+        register(contract, accounts(0));
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            token_id.to_string(),
+            accounts(0),
+            1,
+            format!(
+                r#"{{"sale_type": "dutch_auction", "starting_price": "1000", "floor_price": "200", "end_at": "2000", "curve": {}}}"#,
+                curve
+            ),
+        );
+    }
+
+    #[test]
+    fn test_dutch_auction_price_curves() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        add_dutch_auction(&mut contract, "linear", r#"{"type": "linear"}"#);
+        register(&mut contract, accounts(0));
+        add_dutch_auction(&mut contract, "stepped", r#"{"type": "stepped", "steps": 4}"#);
+        let linear = format!("{}{}{}", accounts(4), DELIMETER, "linear");
+        let stepped = format!("{}{}{}", accounts(4), DELIMETER, "stepped");
+
+        let prices_at = |timestamp: u64| {
+            testing_env!(get_context(accounts(1)).block_timestamp(timestamp).build());
+            (
+                contract.get_current_price(linear.clone()).unwrap().0,
+                contract.get_current_price(stepped.clone()).unwrap().0,
+            )
+        };
+        assert_eq!(prices_at(1_000), (1000, 1000));
+        assert_eq!(prices_at(1_100), (920, 1000));
+        assert_eq!(prices_at(1_250), (800, 800));
+        assert_eq!(prices_at(1_600), (520, 600));
+        assert_eq!(prices_at(2_000), (200, 200));
+        // the price stays at the floor once the auction is over
+        assert_eq!(prices_at(5_000), (200, 200));
+    }
+
+    #[test]
+    fn test_purchase_dutch_auction_at_current_price() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        add_dutch_auction(&mut contract, "token", r#"{"type": "linear"}"#);
+
+        testing_env!(get_context(accounts(1))
+            .block_timestamp(1_500)
+            .attached_deposit(1000)
+            .build());
+        contract.purchase_nft(accounts(4), "token".to_string());
+        let resolves = function_calls("resolve_purchase_approval");
+        assert_eq!(resolves[0].1["purchase"]["price"], "600");
+        assert_eq!(resolves[0].1["purchase"]["deposit"], "1000");
+    }
+
+    #[test]
+    #[should_panic(expected = "starting_price must be above floor_price")]
+    fn test_dutch_auction_floor_above_start() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        register(&mut contract, accounts(0));
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            "token".to_string(),
+            accounts(0),
+            1,
+            r#"{"sale_type": "dutch_auction", "starting_price": "100", "floor_price": "200", "end_at": "2000", "curve": {"type": "linear"}}"#
+                .to_string(),
+        );
+    }
+
+    // Lists `token_id` as a sealed auction taking bids until 2000 and reveals until 3000
+    fn add_sealed_auction(contract: &mut Marketplace, token_id: &str, terms: &str) {
+        register(contract, accounts(0));
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            token_id.to_string(),
+            accounts(0),
+            1,
+            format!(
+                r#"{{"sale_type": "sealed_auction", "starting_price": "100", "end_at": "2000", "reveal_end_at": "3000"{}}}"#,
+                terms
+            ),
+        );
+    }
+
+    // `bidder_id` commits to bidding `amount` with `salt`, escrowing `deposit`
+    fn commit_bid(
+        contract: &mut Marketplace,
+        bidder_id: AccountId,
+        amount: u128,
+        salt: &str,
+        deposit: u128,
+    ) {
+        register(contract, bidder_id.clone());
+        testing_env!(get_context(bidder_id)
+            .block_timestamp(1_500)
+            .attached_deposit(deposit)
+            .build());
+        let bid_hash = env::sha256(format!("{}:{}", amount, salt).as_bytes());
+        contract.commit_bid(accounts(4), "token".to_string(), bid_hash.into());
+    }
+
+    #[test]
+    fn test_sealed_vickrey_auction() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        add_sealed_auction(&mut contract, "token", r#", "vickrey": true, "unrevealed_bids": "forfeit""#);
+        commit_bid(&mut contract, accounts(1), 500, "first", 600);
+        commit_bid(&mut contract, accounts(2), 300, "second", 300);
+        commit_bid(&mut contract, accounts(5), 900, "never", 900);
+
+        // bids can't be revealed while bidding is still open
+        testing_env!(get_context(accounts(1)).block_timestamp(1_900).build());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.reveal_bid(accounts(4), "token".to_string(), U128(500), "first".to_string())
+        }));
+        assert!(result.is_err());
+
+        // the winning bid keeps only its amount in escrow
+        testing_env!(get_context(accounts(1)).block_timestamp(2_500).build());
+        contract.reveal_bid(accounts(4), "token".to_string(), U128(500), "first".to_string());
+        assert_eq!(transferred_to(&accounts(1)), 100);
+        assert_eq!(events()[0]["event"], "bid_revealed");
+
+        // the losing bid is refunded in full
+        testing_env!(get_context(accounts(2)).block_timestamp(2_500).build());
+        contract.reveal_bid(accounts(4), "token".to_string(), U128(300), "second".to_string());
+        assert_eq!(transferred_to(&accounts(2)), 300);
+
+        // the winner pays the second highest bid
+        testing_env!(get_context(accounts(2)).block_timestamp(3_000).build());
+        contract.settle_auction(accounts(4), "token".to_string());
+        let resolves = function_calls("resolve_purchase");
+        assert_eq!(resolves[0].1["purchase"]["buyer_id"], accounts(1).to_string());
+        assert_eq!(resolves[0].1["purchase"]["price"], "300");
+        assert_eq!(resolves[0].1["purchase"]["deposit"], "500");
+
+        // the bid that was never revealed goes to the seller
+        contract.release_unrevealed_bid(accounts(4), "token".to_string(), accounts(5));
+        assert_eq!(transferred_to(&accounts(0)), 900);
+        assert_eq!(transferred_to(&accounts(5)), 0);
+        let listing_id = format!("{}{}{}", accounts(4), DELIMETER, "token");
+        assert!(contract.sealed_bids.get(&listing_id).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Revealed bid doesn't match the commitment")]
+    fn test_reveal_bid_with_wrong_salt() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        add_sealed_auction(&mut contract, "token", "");
+        commit_bid(&mut contract, accounts(1), 500, "first", 500);
+
+        testing_env!(get_context(accounts(1)).block_timestamp(2_500).build());
+        contract.reveal_bid(accounts(4), "token".to_string(), U128(500), "other".to_string());
+    }
+
+    #[test]
+    fn test_sealed_auction_refunds_unrevealed_bids() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        add_sealed_auction(&mut contract, "token", "");
+        commit_bid(&mut contract, accounts(1), 500, "first", 500);
+
+        // sealed auctions don't take open bids, and can't be called off once someone has committed
+        testing_env!(get_context(accounts(2))
+            .block_timestamp(1_500)
+            .attached_deposit(1000)
+            .build());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.bid(accounts(4), "token".to_string())
+        }));
+        assert!(result.is_err());
+        testing_env!(get_context(accounts(0)).block_timestamp(1_500).build());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.cancel_listing(accounts(4), "token".to_string())
+        }));
+        assert!(result.is_err());
+
+        testing_env!(get_context(accounts(2)).block_timestamp(3_000).build());
+        match contract.settle_auction(accounts(4), "token".to_string()) {
+            PromiseOrValue::Value(paid) => assert_eq!(paid, U128(0)),
+            _ => panic!("Expected a value"),
+        }
+        contract.release_unrevealed_bid(accounts(4), "token".to_string(), accounts(1));
+        assert_eq!(transferred_to(&accounts(1)), 500);
+        assert_eq!(transferred_to(&accounts(0)), 0);
+    }
+
+    #[test]
+    fn test_bid_history() {
+        let mut context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(1000);
+        add_auction(&mut contract, "auction", 100);
+        let listing_id = format!("{}{}{}", accounts(4), DELIMETER, "auction");
+        for _ in 0..2 {
+            register(&mut contract, accounts(1));
+            register(&mut contract, accounts(2));
+        }
+
+        // bidders take turns outbidding each other until the history is full, and then once more
+        let mut storage_used = 0;
+        for round in 0..=MAX_BID_HISTORY {
+            let bidder_id = accounts(1 + (round % 2) as usize);
+            storage_used = contract.storage_used.get(&accounts(1)).unwrap();
+            testing_env!(context
+                .block_timestamp(1_001 + round)
+                .attached_deposit(100 + u128::from(round))
+                .predecessor_account_id(bidder_id)
+                .build());
+            contract.bid(accounts(4), "auction".to_string());
+        }
+        // the last bid took the place of accounts(1)'s first one, so they're using the same storage as before
+        assert_eq!(contract.storage_used.get(&accounts(1)), Some(storage_used));
+
+        // the oldest bid was dropped to make room
+        let bids = contract.get_bids(listing_id.clone(), None, None);
+        assert_eq!(bids.len() as u64, MAX_BID_HISTORY);
+        assert_eq!(bids[0].bidder_id, accounts(2));
+        assert_eq!(bids[0].amount, U128(101));
+        assert_eq!(bids[0].timestamp, U64(1_002));
+        assert_eq!(bids.last().unwrap().amount, U128(100 + u128::from(MAX_BID_HISTORY)));
+
+        let page = contract.get_bids(listing_id.clone(), Some(U128(10)), Some(2));
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0].amount, U128(111));
+        assert_eq!(page[1].amount, U128(112));
+
+        assert!(contract.get_bids("unknown".to_string(), None, None).is_empty());
+
+        // the history outlives the listing, in case the sale is disputed
+        testing_env!(context
+            .signer_account_id(accounts(0))
+            .predecessor_account_id(accounts(0))
+            .build());
+        contract.cancel_listing(accounts(4), "auction".to_string());
+        assert_eq!(contract.get_bids(listing_id.clone(), None, None).len() as u64, MAX_BID_HISTORY);
+        assert!(contract.storage_used.get(&accounts(1)).unwrap() > 0);
+
+        // until the token is listed again, when the bidders get back the storage it was using
+        register(&mut contract, accounts(0));
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            "auction".to_string(),
+            accounts(0),
+            2,
+            r#"{"sale_type": "fixed_price", "price": "500"}"#.to_string(),
+        );
+        assert!(contract.get_bids(listing_id, None, None).is_empty());
+        assert_eq!(contract.storage_used.get(&accounts(1)), Some(0));
+        assert_eq!(contract.storage_used.get(&accounts(2)), Some(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Insufficient storage paid")]
+    fn test_bid_without_storage() {
+        let mut context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(1000);
+        add_auction(&mut contract, "auction", 100);
+
+        testing_env!(context
+            .block_timestamp(1_500)
+            .attached_deposit(100)
+            .predecessor_account_id(accounts(5))
+            .build());
+        contract.bid(accounts(4), "auction".to_string());
+    }
+
+    #[test]
+    fn test_create_listing() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        register(&mut contract, accounts(0));
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            "token".to_string(),
+            accounts(0),
+            1,
+            r#"{"sale_type": "fixed_price", "price": "500"}"#.to_string(),
+        );
+        let listing_id = format!("{}{}{}", accounts(4), DELIMETER, "token");
+
+        // another account can't take over the listing
+        testing_env!(get_context(accounts(1)).block_timestamp(1_000).build());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.create_listing(accounts(4), "token".to_string(), 1, 3_000, 2_000, true)
+        }));
+        assert!(result.is_err());
+        assert_eq!(contract.get_sale(listing_id.clone()).unwrap().seller, accounts(0));
+
+        // the owner can turn it into an auction
+        testing_env!(get_context(accounts(0)).block_timestamp(1_000).build());
+        contract.create_listing(accounts(4), "token".to_string(), 100, 3_000, 2_000, true);
+        let sale = contract.get_sale(listing_id).unwrap();
+        assert!(sale.is_auction);
+        assert_eq!(sale.starting_price, 100);
+        assert_eq!(sale.started_at, 2_000);
+        assert_eq!(sale.end_at, 3_000);
+        assert_eq!(events()[0]["event"], "listing_updated");
+    }
+
+    #[test]
+    fn test_create_listing_resets_auction_terms() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        register(&mut contract, accounts(0));
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            "token".to_string(),
+            accounts(0),
+            1,
+            r#"{"sale_type": "auction", "starting_price": "100", "end_at": "5000", "extension_window": "0",
+                "reserve_price": "400", "buy_now_price": "1000"}"#
+                .to_string(),
+        );
+        let listing_id = format!("{}{}{}", accounts(4), DELIMETER, "token");
+
+        // the reserve and buy now price don't carry over to the fixed price listing
+        testing_env!(get_context(accounts(0)).block_timestamp(1_000).build());
+        contract.create_listing(accounts(4), "token".to_string(), 2_000, 0, 0, false);
+        let sale = contract.get_sale(listing_id.clone()).unwrap();
+        assert_eq!(sale.reserve_price, 0);
+        assert_eq!(sale.buy_now_price, None);
+        assert_eq!(sale.extension_window, 0);
+        assert_eq!(sale.extension_duration, 0);
+
+        // and an auction gets the default extension
+        contract.create_listing(accounts(4), "token".to_string(), 100, 3_000, 2_000, true);
+        let sale = contract.get_sale(listing_id).unwrap();
+        assert_eq!(sale.extension_window, contract.auction_extension_window);
+        assert_eq!(sale.extension_duration, contract.auction_extension_duration);
+    }
+
+    #[test]
+    #[should_panic(expected = "Price must be greater than 0")]
+    fn test_set_price_to_zero() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        add_expiring_listing(&mut contract, "token");
+
+        testing_env!(get_context(accounts(0)).build());
+        contract.set_price(accounts(4), "token".to_string(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "started_at can't be in the past")]
+    fn test_create_listing_starting_in_the_past() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        add_ft_listing(&mut contract, "token");
+
+        testing_env!(get_context(accounts(0)).block_timestamp(1_000).build());
+        contract.create_listing(accounts(4), "token".to_string(), 100, 3_000, 500, true);
+    }
+
+    #[test]
+    #[should_panic(expected = "Price must be greater than 0")]
+    fn test_create_listing_without_price() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        add_ft_listing(&mut contract, "token");
+
+        testing_env!(get_context(accounts(0)).block_timestamp(1_000).build());
+        contract.create_listing(accounts(4), "token".to_string(), 0, 0, 0, false);
+    }
+
+    #[test]
+    fn test_purchase_refunded_when_approval_revoked() {
+        let context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+        register(&mut contract, accounts(0));
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            "token".to_string(),
+            accounts(0),
+            7,
+            r#"{"sale_type": "fixed_price", "price": "500"}"#.to_string(),
+        );
+
+        testing_env!(get_context(accounts(1)).attached_deposit(500).build());
+        contract.purchase_nft(accounts(4), "token".to_string());
+        let approvals = function_calls("nft_is_approved");
+        assert_eq!(approvals[0].1["approved_account_id"], accounts(0).to_string());
+        assert_eq!(approvals[0].1["approval_id"], 7);
+        assert!(function_calls("nft_transfer_payout").is_empty());
+
+        // the seller revoked the approval, so the listing is dropped and the buyer gets their deposit back
+        resolve_context(&context, PromiseResult::Successful(b"false".to_vec()));
+        contract.resolve_purchase_approval(
+            purchase(accounts(4), "token", accounts(0), accounts(1), 500, 500),
+            7,
+        );
+        assert!(function_calls("nft_transfer_payout").is_empty());
+        assert_eq!(transferred_to(&accounts(1)), 500);
+        assert_eq!(events()[0]["event"], "sale_failed");
+        assert_eq!(contract.get_supply_sales(), U64(0));
+    }
+
+    #[test]
+    fn test_bid_on_revoked_approval_cancels_auction() {
+        let context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+        add_auction(&mut contract, "auction", 100);
+
+        testing_env!(get_context(accounts(1))
+            .block_timestamp(1_500)
+            .attached_deposit(200)
+            .build());
+        contract.bid(accounts(4), "auction".to_string());
+        let resolves = function_calls("resolve_bid_approval");
+        assert_eq!(resolves[0].1["approval_id"], 1);
+
+        // an approved listing keeps the bid
+        resolve_context(&context, PromiseResult::Successful(b"true".to_vec()));
+        contract.resolve_bid_approval(accounts(4), "auction".to_string(), 1);
+        assert_eq!(contract.get_supply_sales(), U64(1));
+
+        // one that lost its approval is cancelled and the bid refunded
+        resolve_context(&context, PromiseResult::Successful(b"false".to_vec()));
+        contract.resolve_bid_approval(accounts(4), "auction".to_string(), 1);
+        assert_eq!(contract.get_supply_sales(), U64(0));
+        assert_eq!(transferred_to(&accounts(1)), 200);
+        assert_eq!(events()[0]["event"], "listing_cancelled");
+    }
+
+    #[test]
+    fn test_remove_stale_listing_rewards_caller() {
+        let context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+        register(&mut contract, accounts(0));
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            "token".to_string(),
+            accounts(0),
+            7,
+            r#"{"sale_type": "fixed_price", "price": "500"}"#.to_string(),
+        );
+        let deposit = contract.storage_balance_of(accounts(0)).unwrap().total.0;
+
+        testing_env!(get_context(accounts(2)).build());
+        contract.remove_stale_listing(accounts(4), "token".to_string());
+        assert_eq!(function_calls("nft_is_approved")[0].0, accounts(4));
+
+        // a listing that's still approved stays put
+        resolve_context(&context, PromiseResult::Successful(b"true".to_vec()));
+        assert!(!contract.resolve_stale_listing(accounts(4), "token".to_string(), 7, accounts(2)));
+        assert_eq!(contract.get_supply_sales(), U64(1));
+
+        // one that isn't is removed, and the caller is paid out of the seller's storage balance
+        resolve_context(&context, PromiseResult::Successful(b"false".to_vec()));
+        assert!(contract.resolve_stale_listing(accounts(4), "token".to_string(), 7, accounts(2)));
+        assert_eq!(contract.get_supply_sales(), U64(0));
+        assert_eq!(transferred_to(&accounts(2)), STALE_LISTING_REWARD);
+        assert_eq!(
+            contract.storage_balance_of(accounts(0)).unwrap().total.0,
+            deposit - STALE_LISTING_REWARD
+        );
+        let event = events().pop().unwrap();
+        assert_eq!(event["event"], "stale_listing_removed");
+        assert_eq!(event["data"]["caller_id"], accounts(2).to_string());
+    }
+
+    #[test]
+    fn test_nft_on_revoke_cancels_listing() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(10);
+        add_auction(&mut contract, "auction", 100);
+        testing_env!(get_context(accounts(1))
+            .block_timestamp(1_500)
+            .attached_deposit(200)
+            .build());
+        contract.bid(accounts(4), "auction".to_string());
+
+        // only the nft contract the listing belongs to can revoke it
+        testing_env!(get_context(accounts(2)).build());
+        contract.nft_on_revoke("auction".to_string());
+        assert_eq!(contract.get_supply_sales(), U64(1));
+
+        testing_env!(get_context(accounts(4)).build());
+        contract.nft_on_revoke("auction".to_string());
+        assert_eq!(contract.get_supply_sales(), U64(0));
+        assert_eq!(transferred_to(&accounts(1)), 200);
+    }
+
+    // Lists `token_id` for a fixed price of 500 that expires at 2_000
+    fn add_expiring_listing(contract: &mut Marketplace, token_id: &str) {
+        register(contract, accounts(0));
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            token_id.to_string(),
+            accounts(0),
+            1,
+            r#"{"sale_type": "fixed_price", "price": "500", "expires_at": "2000"}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_expired_listing_hidden_from_views() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(10);
+        add_expiring_listing(&mut contract, "token");
+        let sale = contract
+            .get_sale(format!("{}{}{}", accounts(4), DELIMETER, "token"))
+            .unwrap();
+        assert_eq!(sale.end_at, 2_000);
+
+        testing_env!(get_context(accounts(3)).block_timestamp(1_999).build());
+        assert_eq!(contract.get_sales_by_owner_id(accounts(0), None, Some(10)).len(), 1);
+        assert_eq!(contract.get_sales_by_nft_contract_id(accounts(4), None, Some(10)).len(), 1);
+
+        testing_env!(get_context(accounts(3)).block_timestamp(2_000).build());
+        assert!(contract.get_sales_by_owner_id(accounts(0), None, Some(10)).is_empty());
+        assert!(contract.get_sales_by_nft_contract_id(accounts(4), None, Some(10)).is_empty());
+    }
+
+    #[test]
+    fn test_views_page_over_unexpired_listings() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(10);
+        add_expiring_listing(&mut contract, "a");
+        for token_id in ["b", "c"] {
+            register(&mut contract, accounts(0));
+            testing_env!(approve_context(accounts(0)).build());
+            contract.nft_on_approve(
+                token_id.to_string(),
+                accounts(0),
+                1,
+                r#"{"sale_type": "fixed_price", "price": "500"}"#.to_string(),
+            );
+        }
+
+        // once "a" has expired, pages are counted over the listings that are left
+        testing_env!(get_context(accounts(3)).block_timestamp(2_000).build());
+        let token_ids = |sales: Vec<Listing>| -> Vec<String> {
+            sales.into_iter().map(|sale| sale.token_id).collect()
+        };
+        assert_eq!(token_ids(contract.get_sales_by_owner_id(accounts(0), None, Some(1))), ["b"]);
+        assert_eq!(
+            token_ids(contract.get_sales_by_owner_id(accounts(0), Some(U128(1)), Some(1))),
+            ["c"]
+        );
+        assert_eq!(
+            token_ids(contract.get_sales_by_nft_contract_id(accounts(4), Some(U128(1)), Some(1))),
+            ["c"]
+        );
+        assert_eq!(contract.get_supply_by_owner_id(accounts(0)), U64(2));
+        assert_eq!(contract.get_supply_by_nft_contract_id(accounts(4)), U64(2));
+        assert_eq!(contract.get_supply_sales(), U64(3));
+    }
+
+    #[test]
+    #[should_panic(expected = "Listing has expired")]
+    fn test_purchase_expired_listing() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(10);
+        add_expiring_listing(&mut contract, "token");
+
+        testing_env!(get_context(accounts(1))
+            .block_timestamp(2_000)
+            .attached_deposit(500)
+            .build());
+        contract.purchase_nft(accounts(4), "token".to_string());
+    }
+
+    #[test]
+    fn test_prune_expired_listings() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(10);
+        add_expiring_listing(&mut contract, "expiring");
+        register(&mut contract, accounts(0));
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            "forever".to_string(),
+            accounts(0),
+            2,
+            r#"{"sale_type": "fixed_price", "price": "500"}"#.to_string(),
+        );
+        let listing_ids = vec![
+            format!("{}{}{}", accounts(4), DELIMETER, "expiring"),
+            format!("{}{}{}", accounts(4), DELIMETER, "forever"),
+            format!("{}{}{}", accounts(4), DELIMETER, "missing"),
+        ];
+
+        // nothing has expired yet
+        testing_env!(get_context(accounts(2)).block_timestamp(1_500).build());
+        assert_eq!(contract.prune_expired_listings(listing_ids.clone()), 0);
+
+        testing_env!(get_context(accounts(2)).block_timestamp(2_000).build());
+        assert_eq!(contract.prune_expired_listings(listing_ids), 1);
+        assert_eq!(contract.get_supply_sales(), U64(1));
+        assert_eq!(contract.get_supply_by_owner_id(accounts(0)), U64(1));
+        assert_eq!(events()[0]["event"], "listing_cancelled");
+    }
+
+    #[test]
+    #[should_panic(expected = "expires_at must be in the future")]
+    fn test_nft_on_approve_expired_listing() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(10);
+        register(&mut contract, accounts(0));
+        testing_env!(approve_context(accounts(0)).build());
+        contract.nft_on_approve(
+            "token".to_string(),
+            accounts(0),
+            1,
+            r#"{"sale_type": "fixed_price", "price": "500", "expires_at": "1000"}"#.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_two_step_ownership_transfer() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(10);
+        assert_eq!(contract.get_owner(), accounts(3));
+
+        testing_env!(get_context(accounts(3)).attached_deposit(1).build());
+        contract.propose_owner(Some(accounts(1)));
+        assert_eq!(contract.get_proposed_owner(), Some(accounts(1)));
+        assert_eq!(events()[0]["event"], "owner_proposed");
+
+        // nobody else can take it
+        testing_env!(get_context(accounts(2)).attached_deposit(1).build());
+        let result =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| contract.accept_owner()));
+        assert!(result.is_err());
+
+        testing_env!(get_context(accounts(1)).attached_deposit(1).build());
+        contract.accept_owner();
+        assert_eq!(contract.get_owner(), accounts(1));
+        assert_eq!(contract.get_proposed_owner(), None);
+        let event = events().pop().unwrap();
+        assert_eq!(event["event"], "owner_transferred");
+        assert_eq!(event["data"]["owner_id"], accounts(3).to_string());
+        assert_eq!(event["data"]["new_owner_id"], accounts(1).to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn test_propose_owner_not_owner() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(10);
+
+        testing_env!(get_context(accounts(1)).attached_deposit(1).build());
+        contract.propose_owner(Some(accounts(1)));
+    }
+
+    #[test]
+    fn test_admin_roles_are_scoped() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(10);
+        contract.grant_role(accounts(1), AdminRole::Allowlist);
+        contract.grant_role(accounts(2), AdminRole::Fees);
+        assert_eq!(events()[0]["event"], "role_granted");
+        assert_eq!(events()[0]["data"]["role"], "allowlist");
+        assert_eq!(contract.get_roles(accounts(1)), vec![AdminRole::Allowlist]);
+        assert_eq!(contract.get_admins(None, Some(10)).len(), 2);
+
+        // an allowlist admin can manage the accepted fungible tokens
+        testing_env!(get_context(accounts(1)).build());
+        contract.add_ft_token_ids(vec![accounts(5)]);
+        assert_eq!(contract.get_supported_ft_token_ids(), vec![accounts(5)]);
+
+        // a fee admin can't
+        testing_env!(get_context(accounts(2)).build());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.remove_ft_token_ids(vec![accounts(5)])
+        }));
+        assert!(result.is_err());
+
+        // and neither can an admin whose role was revoked
+        testing_env!(get_context(accounts(3)).build());
+        contract.revoke_role(accounts(1), AdminRole::Allowlist);
+        assert_eq!(events()[0]["event"], "role_revoked");
+        assert!(contract.get_roles(accounts(1)).is_empty());
+        testing_env!(get_context(accounts(1)).build());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.remove_ft_token_ids(vec![accounts(5)])
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_set_owner_cut_timelocks_increases() {
+        testing_env!(get_context(accounts(3)).block_timestamp(1_000).build());
+        let mut contract = Marketplace::new(500);
+
+        // a decrease applies straight away
+        contract.set_owner_cut(200);
+        assert_eq!(contract.get_fee_config().owner_cut, 200);
+        assert_eq!(contract.internal_market_fee(&accounts(4), 1000), 20);
+
+        // an increase waits out the timelock
+        contract.set_owner_cut(800);
+        let fee_config = contract.get_fee_config();
+        assert_eq!(fee_config.owner_cut, 200);
+        assert_eq!(fee_config.pending_owner_cut, Some(800));
+        assert_eq!(
+            fee_config.pending_effective_at,
+            Some(U64(1_000 + fees::OWNER_CUT_TIMELOCK))
+        );
+        assert_eq!(events()[0]["event"], "owner_cut_changed");
+
+        testing_env!(get_context(accounts(3))
+            .block_timestamp(1_000 + fees::OWNER_CUT_TIMELOCK)
+            .build());
+        let fee_config = contract.get_fee_config();
+        assert_eq!(fee_config.owner_cut, 800);
+        assert_eq!(fee_config.pending_owner_cut, None);
+        assert_eq!(contract.internal_market_fee(&accounts(4), 1000), 80);
+    }
+
+    #[test]
+    #[should_panic(expected = "owner_cut can't be more than 1000")]
+    fn test_set_owner_cut_above_cap() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(500);
+        contract.set_owner_cut(1_001);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or an admin with the Fees role can call this method")]
+    fn test_set_owner_cut_not_fees_admin() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(500);
+        contract.grant_role(accounts(1), AdminRole::Allowlist);
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.set_owner_cut(100);
+    }
+
+    #[test]
+    fn test_fees_paid_to_treasury() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        contract.set_treasury(accounts(5));
+        assert_eq!(contract.get_fee_config().treasury_id, accounts(5));
+        assert_eq!(events()[0]["event"], "treasury_changed");
+
+        resolve_context(
+            &get_context(accounts(3)),
+            PromiseResult::Successful(b"{\"payout\":{}}".to_vec()),
+        );
+        contract.resolve_purchase(purchase(
+            accounts(4),
+            "token",
+            accounts(0),
+            accounts(1),
+            1000,
+            1000,
+        ));
+        assert_eq!(transferred_to(&accounts(5)), 100);
+        assert_eq!(transferred_to(&accounts(3)), 0);
+    }
+
+    #[test]
+    fn test_collection_fee_overrides_owner_cut() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        contract.set_collection_config(
+            accounts(4),
+            serde_json::from_str(r#"{"fee": 250, "verified": true}"#).unwrap(),
+        );
+        assert!(contract.get_collection_config(accounts(4)).unwrap().verified);
+        assert_eq!(events()[0]["event"], "collection_config_set");
+        assert_eq!(contract.internal_market_fee(&accounts(4), 1000), 25);
+        // other collections still pay owner_cut
+        assert_eq!(contract.internal_market_fee(&accounts(5), 1000), 100);
+
+        add_expiring_listing(&mut contract, "token");
+        testing_env!(get_context(accounts(1)).attached_deposit(500).build());
+        contract.purchase_nft(accounts(4), "token".to_string());
+        let resolves = function_calls("resolve_purchase_approval");
+        let sale: Purchase = serde_json::from_value(resolves[0].1["purchase"].clone()).unwrap();
+        assert_eq!(sale.marketplace_fee, U128(12));
+
+        // the fee the sale started with is the one it's settled with
+        testing_env!(get_context(accounts(3)).build());
+        contract.remove_collection_config(accounts(4));
+        // going back to owner_cut raises the fee, so the old one stays until the timelock has passed
+        let config = contract.get_collection_config(accounts(4)).unwrap();
+        assert!(!config.verified);
+        assert_eq!(config.fee, Some(250));
+        assert_eq!(contract.internal_market_fee(&accounts(4), 1000), 25);
+        testing_env!(get_context(accounts(3))
+            .block_timestamp(fees::OWNER_CUT_TIMELOCK)
+            .build());
+        assert_eq!(contract.internal_market_fee(&accounts(4), 1000), 100);
+
+        resolve_context(
+            &get_context(accounts(3)),
+            PromiseResult::Successful(b"{\"payout\":{}}".to_vec()),
+        );
+        contract.resolve_purchase(sale);
+        assert_eq!(transferred_to(&accounts(3)), 12);
+        assert_eq!(transferred_to(&accounts(0)), 488);
+    }
+
+    #[test]
+    fn test_collection_fee_increase_waits_for_timelock() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        contract.set_collection_config(
+            accounts(4),
+            serde_json::from_str(r#"{"fee": 50}"#).unwrap(),
+        );
+        assert_eq!(contract.internal_market_fee(&accounts(4), 1000), 5);
+
+        // raising the fee leaves the old one in place until the timelock has passed
+        contract.set_collection_config(
+            accounts(4),
+            serde_json::from_str(r#"{"fee": 800}"#).unwrap(),
+        );
+        let config = contract.get_collection_config(accounts(4)).unwrap();
+        assert_eq!(config.fee, Some(50));
+        assert_eq!(
+            config.pending_fee,
+            Some(collections::PendingCollectionFee {
+                fee: Some(800),
+                effective_at: U64(fees::OWNER_CUT_TIMELOCK),
+            })
+        );
+        assert_eq!(contract.internal_market_fee(&accounts(4), 1000), 5);
+
+        testing_env!(get_context(accounts(3))
+            .block_timestamp(fees::OWNER_CUT_TIMELOCK)
+            .build());
+        assert_eq!(contract.internal_market_fee(&accounts(4), 1000), 80);
+
+        // lowering it applies straight away
+        contract.set_collection_config(
+            accounts(4),
+            serde_json::from_str(r#"{"fee": 100}"#).unwrap(),
+        );
+        let config = contract.get_collection_config(accounts(4)).unwrap();
+        assert_eq!(config.pending_fee, None);
+        assert_eq!(contract.internal_market_fee(&accounts(4), 1000), 10);
+
+        // and a pending fee can't be set by the caller
+        let config: CollectionConfig = serde_json::from_str(
+            r#"{"fee": 100, "pending_fee": {"fee": 1000, "effective_at": "0"}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.pending_fee, None);
+    }
+
+    #[test]
+    fn test_collection_fallback_royalties() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        contract.set_collection_config(
+            accounts(4),
+            serde_json::from_str(&format!(
+                r#"{{"fallback_royalties": {{"{}": 500}}}}"#,
+                accounts(2)
+            ))
+            .unwrap(),
+        );
+
+        // the contract doesn't implement nft_transfer_payout, so the token is sold with nft_transfer
+        contract.process_purchase(
+            purchase(accounts(4), "token", accounts(0), accounts(1), 1000, 1000),
+            1,
+        );
+        assert!(function_calls("nft_transfer_payout").is_empty());
+        let transfers = function_calls("nft_transfer");
+        assert_eq!(transfers[0].0, accounts(4));
+        assert_eq!(transfers[0].1["receiver_id"], accounts(1).to_string());
+        assert_eq!(transfers[0].1["approval_id"], 1);
+
+        // and the royalties come from the config
+        resolve_context(&get_context(accounts(3)), PromiseResult::Successful(vec![]));
+        contract.resolve_purchase(purchase(
+            accounts(4),
+            "token",
+            accounts(0),
+            accounts(1),
+            1000,
+            1000,
+        ));
+        assert_eq!(transferred_to(&accounts(3)), 100);
+        assert_eq!(transferred_to(&accounts(2)), 45);
+        assert_eq!(transferred_to(&accounts(0)), 855);
+    }
+
+    #[test]
+    #[should_panic(expected = "Fallback royalties can't add up to more than 5000")]
+    fn test_collection_fallback_royalties_above_cap() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        contract.set_collection_config(
+            accounts(4),
+            serde_json::from_str(&format!(
+                r#"{{"fallback_royalties": {{"{}": 3000, "{}": 3000}}}}"#,
+                accounts(1),
+                accounts(2)
+            ))
+            .unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_pause_areas_separately() {
+        let context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(10);
+        contract.grant_role(accounts(2), AdminRole::Pause);
+        add_auction(&mut contract, "auction", 100);
+
+        testing_env!(get_context(accounts(2)).build());
+        contract.set_paused(PauseArea::Bidding, true);
+        assert!(contract.get_paused().bidding);
+        assert!(!contract.get_paused().buying);
+        let event = events().pop().unwrap();
+        assert_eq!(event["event"], "pause_toggled");
+        assert_eq!(event["data"]["area"], "bidding");
+        assert_eq!(event["data"]["paused"], true);
+
+        testing_env!(get_context(accounts(1))
+            .block_timestamp(1_500)
+            .attached_deposit(200)
+            .build());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.bid(accounts(4), "auction".to_string())
+        }));
+        assert!(result.is_err());
+
+        // storage can still be withdrawn while it's paused
+        testing_env!(get_context(accounts(2)).attached_deposit(1).build());
+        contract.storage_withdraw(None);
+        assert_eq!(transferred_to(&accounts(2)), MIN_REQUIRED_STORAGE_YOCTO);
+
+        testing_env!(get_context(accounts(2)).build());
+        contract.set_paused(PauseArea::Bidding, false);
+        testing_env!(get_context(accounts(1))
+            .block_timestamp(1_500)
+            .attached_deposit(200)
+            .build());
+        contract.bid(accounts(4), "auction".to_string());
+        let listing_id = format!("{}{}{}", accounts(4), DELIMETER, "auction");
+        assert_eq!(contract.get_bids(listing_id, None, None).len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Buying is paused")]
+    fn test_purchase_while_buying_paused() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(10);
+        add_expiring_listing(&mut contract, "token");
+        testing_env!(get_context(accounts(3)).build());
+        contract.set_paused(PauseArea::Buying, true);
+
+        testing_env!(get_context(accounts(1)).attached_deposit(500).build());
+        contract.purchase_nft(accounts(4), "token".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Listing is paused")]
+    fn test_nft_on_approve_while_listing_paused() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(10);
+        contract.set_paused(PauseArea::Listing, true);
+        add_expiring_listing(&mut contract, "token");
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or an admin with the Pause role can call this method")]
+    fn test_set_paused_not_pause_admin() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(10);
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.set_paused(PauseArea::Settlement, true);
+    }
+
+    #[test]
+    fn test_collection_allowlist_and_blocklist() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(10);
+        let status = contract.get_collection_status(accounts(4));
+        assert_eq!(status.mode, CollectionMode::Open);
+        assert!(status.can_list);
+
+        // in allowlist mode only allowlisted contracts can list
+        contract.set_collection_mode(CollectionMode::Allowlist);
+        assert_eq!(events()[0]["event"], "collection_mode_changed");
+        assert!(!contract.get_collection_status(accounts(4)).can_list);
+        contract.set_allowlisted(vec![accounts(4)], true);
+        assert_eq!(events().pop().unwrap()["event"], "collections_allowlisted");
+        let status = contract.get_collection_status(accounts(4));
+        assert!(status.allowlisted);
+        assert!(status.can_list);
+        add_expiring_listing(&mut contract, "token");
+        assert_eq!(contract.get_supply_sales(), U64(1));
+
+        // in blocklist mode anything that isn't blocklisted can
+        testing_env!(get_context(accounts(3)).build());
+        contract.set_collection_mode(CollectionMode::Blocklist);
+        assert!(contract.get_collection_status(accounts(5)).can_list);
+        contract.set_blocklisted(vec![accounts(4)], true);
+        let status = contract.get_collection_status(accounts(4));
+        assert!(status.blocklisted);
+        assert!(!status.can_list);
+    }
+
+    #[test]
+    #[should_panic(expected = "is not an approved nft contract")]
+    fn test_nft_on_approve_blocklisted_contract() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(10);
+        contract.set_collection_mode(CollectionMode::Blocklist);
+        contract.set_blocklisted(vec![accounts(4)], true);
+        add_expiring_listing(&mut contract, "token");
+    }
+
+    #[test]
+    fn test_revoked_sealed_auction_refunds_forfeitable_bids() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        add_sealed_auction(&mut contract, "token", r#", "unrevealed_bids": "forfeit""#);
+        commit_bid(&mut contract, accounts(5), 900, "never", 900);
+
+        // the seller pulls the approval before anyone could reveal
+        testing_env!(get_context(accounts(4)).block_timestamp(1_900).build());
+        contract.nft_on_revoke("token".to_string());
+        assert_eq!(contract.get_supply_sales(), U64(0));
+        assert_eq!(transferred_to(&accounts(5)), 900);
+        assert_eq!(transferred_to(&accounts(0)), 0);
+        let listing_id = format!("{}{}{}", accounts(4), DELIMETER, "token");
+        assert!(contract.sealed_bids.get(&listing_id).is_none());
+
+        // so there's nothing left to forfeit once the reveal phase would have ended
+        testing_env!(get_context(accounts(0)).block_timestamp(3_000).build());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.release_unrevealed_bid(accounts(4), "token".to_string(), accounts(5))
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Sealed bids have been committed")]
+    fn test_storage_unregister_with_sealed_bids() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(1000);
+        add_sealed_auction(&mut contract, "token", r#", "unrevealed_bids": "forfeit""#);
+        commit_bid(&mut contract, accounts(5), 900, "never", 900);
+
+        testing_env!(get_context(accounts(0)).attached_deposit(1).build());
+        contract.storage_unregister(Some(true));
+    }
+
+    #[test]
+    fn test_nft_on_approve_by_new_owner_replaces_old_listing() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(10);
+        add_expiring_listing(&mut contract, "token");
+        let available = contract.storage_balance_of(accounts(0)).unwrap().available;
+
+        // the token was sold elsewhere and its new owner lists it
+        register(&mut contract, accounts(1));
+        testing_env!(approve_context(accounts(1)).build());
+        contract.nft_on_approve(
+            "token".to_string(),
+            accounts(1),
+            2,
+            r#"{"sale_type": "fixed_price", "price": "700"}"#.to_string(),
+        );
+        assert_eq!(events().pop().unwrap()["event"], "listing_created");
+        assert_eq!(contract.get_supply_by_owner_id(accounts(0)), U64(0));
+        assert_eq!(contract.get_supply_by_owner_id(accounts(1)), U64(1));
+        assert!(contract.storage_balance_of(accounts(0)).unwrap().available.0 > available.0);
+
+        // so the old seller unregistering doesn't touch the new owner's listing
+        testing_env!(get_context(accounts(0)).attached_deposit(1).build());
+        contract.storage_unregister(Some(true));
+        let sale = contract
+            .get_sale(format!("{}{}{}", accounts(4), DELIMETER, "token"))
+            .unwrap();
+        assert_eq!(sale.seller, accounts(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't unregister the account while its offers or bids are still using storage")]
+    fn test_storage_unregister_with_open_offer() {
+        testing_env!(get_context(accounts(3)).build());
+        let mut contract = Marketplace::new(10);
+        register(&mut contract, accounts(1));
+        testing_env!(get_context(accounts(1))
+            .block_timestamp(1_000)
+            .attached_deposit(500)
+            .build());
+        contract.make_offer(accounts(4), "token".to_string(), U64(2_000));
+
+        testing_env!(get_context(accounts(1)).attached_deposit(1).build());
+        contract.storage_unregister(None);
+    }
+
+    #[test]
+    fn test_failed_ft_transfer_can_be_claimed() {
+        let context = get_context(accounts(3));
+        testing_env!(context.build());
+        let mut contract = Marketplace::new(1000);
+
+        // every ft_transfer is followed by a check that it went through
+        contract.internal_transfer(&Some(accounts(5)), accounts(2), 90);
+        let resolves = function_calls("resolve_ft_transfer");
+        assert_eq!(resolves[0].1["receiver_id"], accounts(2).to_string());
+        assert_eq!(resolves[0].1["amount"], "90");
+
+        resolve_context(&context, PromiseResult::Successful(vec![]));
+        contract.resolve_ft_transfer(accounts(5), accounts(2), U128(90));
+        assert_eq!(contract.get_claimable_ft(accounts(2), accounts(5)), U128(0));
+
+        // the receiver isn't registered with the token, so the tokens are kept for them
+        resolve_context(&context, PromiseResult::Failed);
+        contract.resolve_ft_transfer(accounts(5), accounts(2), U128(90));
+        contract.resolve_ft_transfer(accounts(5), accounts(2), U128(10));
+        assert_eq!(contract.get_claimable_ft(accounts(2), accounts(5)), U128(100));
+        assert_eq!(events()[0]["event"], "ft_transfer_failed");
+
+        testing_env!(get_context(accounts(2)).attached_deposit(1).build());
+        contract.claim_ft(accounts(5));
+        assert_eq!(contract.get_claimable_ft(accounts(2), accounts(5)), U128(0));
+        let transfers = function_calls("ft_transfer");
+        assert_eq!(transfers[0].0, accounts(5));
+        assert_eq!(transfers[0].1["receiver_id"], accounts(2).to_string());
+        assert_eq!(transfers[0].1["amount"], "100");
+        assert_eq!(events()[0]["event"], "ft_claimed");
+    }
 }